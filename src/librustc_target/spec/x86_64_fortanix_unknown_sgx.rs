@@ -36,6 +36,9 @@ pub fn target() -> Result<Target, String> {
         "EH_FRM_HDR_SIZE",
         "TEXT_BASE",
         "TEXT_SIZE",
+        "SSA_FRAME_SIZE",
+        "STACK_SIZE",
+        "SSA_EXINFO",
     ];
     let opts = TargetOptions {
         dynamic_linking: false,
@@ -46,6 +49,12 @@ pub fn target() -> Result<Target, String> {
         cpu: "x86-64".into(),
         features: "+rdrnd,+rdseed".into(),
         position_independent_executables: true,
+        // Backtraces inside the enclave walk the frame pointers
+        eliminate_frame_pointer: false,
+        // Touch every page of large stack frames, so that overflowing a
+        // thread's stack faults on the page below it instead of silently
+        // writing to whatever memory is placed there
+        stack_probes: true,
         pre_link_args: iter::once((
             LinkerFlavor::Gcc,
             PRE_LINK_ARGS.iter().cloned().map(String::from).collect(),
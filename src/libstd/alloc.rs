@@ -209,6 +209,9 @@ pub fn rust_oom(layout: Layout) -> ! {
         unsafe { mem::transmute(hook) }
     };
     hook(layout);
+    #[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+    crate::sys::alloc::abort_alloc_error(layout);
+    #[cfg(not(all(target_vendor = "fortanix", target_env = "sgx")))]
     unsafe { crate::sys::abort_internal(); }
 }
 
@@ -43,6 +43,12 @@ use crate::mem;
 pub fn copy<R: ?Sized, W: ?Sized>(reader: &mut R, writer: &mut W) -> io::Result<u64>
     where R: Read, W: Write
 {
+    // FIXME: On SGX, copying between two streams could keep the data in user
+    // memory, like `std::os::fortanix_sgx::io::copy` does. Taking that path
+    // for some reader and writer types but not others needs specialization,
+    // which libstd doesn't use, since `R` and `W` aren't `'static` and can't
+    // be downcast. The SGX streams would also have to hand over data that
+    // `TcpStream::peek` already read into the enclave.
     let mut buf = unsafe {
         let mut buf: [u8; super::DEFAULT_BUF_SIZE] = mem::uninitialized();
         reader.initializer().initialize(&mut buf);
@@ -586,7 +586,7 @@ impl Write for TcpStream {
         self.0.write_vectored(bufs)
     }
 
-    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    fn flush(&mut self) -> io::Result<()> { self.0.flush() }
 }
 #[stable(feature = "rust1", since = "1.0.0")]
 impl Read for &TcpStream {
@@ -609,7 +609,7 @@ impl Write for &TcpStream {
         self.0.write_vectored(bufs)
     }
 
-    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    fn flush(&mut self) -> io::Result<()> { self.0.flush() }
 }
 
 impl AsInner<net_imp::TcpStream> for TcpStream {
@@ -1399,8 +1399,6 @@ mod tests {
     }
 
     #[test]
-    // FIXME: https://github.com/fortanix/rust-sgx/issues/110
-    #[cfg_attr(target_env = "sgx", ignore)]
     fn close_readwrite_smoke() {
         each_ip(&mut |addr| {
             let a = t!(TcpListener::bind(&addr));
@@ -1598,7 +1596,8 @@ mod tests {
     // FIXME: re-enabled bitrig/openbsd tests once their socket timeout code
     //        no longer has rounding errors.
     #[cfg_attr(any(target_os = "bitrig", target_os = "netbsd", target_os = "openbsd"), ignore)]
-    #[cfg_attr(target_env = "sgx", ignore)] // FIXME: https://github.com/fortanix/rust-sgx/issues/31
+    // Write timeouts are not supported on SGX
+    #[cfg_attr(target_env = "sgx", ignore)]
     #[test]
     fn timeouts() {
         let addr = next_test_ip4();
@@ -1626,7 +1625,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg_attr(target_env = "sgx", ignore)] // FIXME: https://github.com/fortanix/rust-sgx/issues/31
     fn test_read_timeout() {
         let addr = next_test_ip4();
         let listener = t!(TcpListener::bind(&addr));
@@ -1644,7 +1642,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg_attr(target_env = "sgx", ignore)] // FIXME: https://github.com/fortanix/rust-sgx/issues/31
     fn test_read_with_timeout() {
         let addr = next_test_ip4();
         let listener = t!(TcpListener::bind(&addr));
@@ -1720,7 +1717,6 @@ mod tests {
     }
 
     #[test]
-    #[cfg_attr(target_env = "sgx", ignore)]
     fn set_nonblocking() {
         let addr = next_test_ip4();
         let listener = t!(TcpListener::bind(&addr));
@@ -1773,8 +1769,158 @@ mod tests {
         })
     }
 
+    // Connects to a server that writes `data` and closes the connection
+    #[cfg(target_env = "sgx")]
+    fn connect_to_writer(addr: SocketAddr, data: &'static [u8]) -> TcpStream {
+        let srv = t!(TcpListener::bind(&addr));
+        let _t = thread::spawn(move|| {
+            let mut cl = t!(srv.accept()).0;
+            t!(cl.write_all(data));
+        });
+        t!(TcpStream::connect(&addr))
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn peek_then_short_read() {
+        each_ip(&mut |addr| {
+            let mut c = connect_to_writer(addr, b"hello");
+            let mut b = [0; 5];
+            assert_eq!(t!(c.peek(&mut b)), 5);
+            let mut short = [0; 2];
+            assert_eq!(t!(c.read(&mut short)), 2);
+            assert_eq!(&short, b"he");
+            assert_eq!(t!(c.peek(&mut b)), 3);
+            assert_eq!(&b[..3], b"llo");
+            assert_eq!(t!(c.read(&mut b)), 3);
+            assert_eq!(&b[..3], b"llo");
+            assert_eq!(t!(c.read(&mut b)), 0);
+        })
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn peek_then_read_vectored() {
+        each_ip(&mut |addr| {
+            let mut c = connect_to_writer(addr, b"hello");
+            let mut b = [0; 5];
+            assert_eq!(t!(c.peek(&mut b)), 5);
+            let mut first = [0; 3];
+            let mut second = [0; 8];
+            let len = t!(c.read_vectored(&mut [IoVecMut::new(&mut first),
+                                              IoVecMut::new(&mut second)]));
+            assert_eq!(len, 3);
+            assert_eq!(&first, b"hel");
+            let len = t!(c.read_vectored(&mut [IoVecMut::new(&mut first),
+                                              IoVecMut::new(&mut second)]));
+            assert_eq!(len, 2);
+            assert_eq!(&first[..2], b"lo");
+        })
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn peek_then_try_into_raw_fd() {
+        use crate::os::fortanix_sgx::io::{FromRawFd, TryIntoRawFd};
+
+        each_ip(&mut |addr| {
+            let c = connect_to_writer(addr, b"hello");
+            let mut b = [0; 5];
+            assert_eq!(t!(c.peek(&mut b)), 5);
+            // The peeked data would be lost
+            let mut c = c.try_into_raw_fd().unwrap_err();
+            assert_eq!(t!(c.read(&mut b)), 5);
+            let fd = c.try_into_raw_fd().unwrap();
+            drop(unsafe { TcpStream::from_raw_fd(fd) });
+        })
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn copy_writes_peeked() {
+        use crate::os::fortanix_sgx::io::copy;
+
+        each_ip(&mut |addr| {
+            let c = connect_to_writer(addr, b"hello world");
+            let mut b = [0; 5];
+            assert_eq!(t!(c.peek(&mut b)), 5);
+
+            let sink_addr = next_test_ip4();
+            let sink = t!(TcpListener::bind(&sink_addr));
+            let out = t!(TcpStream::connect(&sink_addr));
+            let mut received = t!(sink.accept()).0;
+
+            assert_eq!(t!(copy(&c, &out)), 11);
+            drop(out);
+            let mut data = Vec::new();
+            t!(received.read_to_end(&mut data));
+            assert_eq!(data, b"hello world");
+        })
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn read_after_timeout_gets_late_data() {
+        each_ip(&mut |addr| {
+            let listener = t!(TcpListener::bind(&addr));
+            let mut c = t!(TcpStream::connect(&addr));
+            let mut other_end = t!(listener.accept()).0;
+            t!(c.set_read_timeout(Some(Duration::from_millis(100))));
+
+            let mut b = [0; 5];
+            let kind = c.read(&mut b).err().expect("expected error").kind();
+            assert_eq!(kind, ErrorKind::WouldBlock);
+
+            // The timed out read is still pending and gets this
+            t!(other_end.write_all(b"hello"));
+            t!(c.set_read_timeout(None));
+            assert_eq!(t!(c.read(&mut b)), 5);
+            assert_eq!(&b, b"hello");
+        })
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn nonblocking_read_gets_late_data() {
+        each_ip(&mut |addr| {
+            let listener = t!(TcpListener::bind(&addr));
+            let mut c = t!(TcpStream::connect(&addr));
+            let mut other_end = t!(listener.accept()).0;
+            t!(c.set_nonblocking(true));
+
+            let mut b = [0; 5];
+            let kind = c.read(&mut b).err().expect("expected error").kind();
+            assert_eq!(kind, ErrorKind::WouldBlock);
+
+            t!(other_end.write_all(b"hello"));
+            loop {
+                match c.read(&mut b) {
+                    Ok(len) => {
+                        assert_eq!(len, 5);
+                        break;
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+                    Err(e) => panic!("unexpected error {}", e),
+                }
+            }
+            assert_eq!(&b, b"hello");
+        })
+    }
+
+    #[test]
+    #[cfg(target_env = "sgx")]
+    fn write_timeout_unsupported() {
+        each_ip(&mut |addr| {
+            let _listener = t!(TcpListener::bind(&addr));
+            let c = t!(TcpStream::connect(&addr));
+            let err = c.set_write_timeout(Some(Duration::from_secs(1))).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Other);
+            assert_eq!(t!(c.write_timeout()), None);
+            t!(c.set_write_timeout(None));
+        })
+    }
+
     #[test]
-    #[cfg_attr(target_env = "sgx", ignore)] // FIXME: https://github.com/fortanix/rust-sgx/issues/31
     fn connect_timeout_valid() {
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = listener.local_addr().unwrap();
@@ -2,6 +2,65 @@
 //!
 //! This includes functions to deal with memory isolation, usercalls, and the
 //! SGX instruction set.
+//!
+//! Every thread runs on a thread control structure (TCS) of the enclave.
+//! When a thread exits, its TCS waits up to 5 seconds for a new thread to be
+//! spawned, so that `thread::spawn` doesn't have to ask userspace to enter
+//! the enclave again. At most 16 TCSs wait like this, and userspace counts
+//! them as in use while they do.
+//!
+//! # Limitations
+//!
+//! The usercall ABI is defined outside of the standard library. These
+//! features need userspace to do more than the ABI allows, and are not
+//! available yet:
+//!
+//! * The `exit` usercall only carries a flag saying whether the enclave
+//!   panicked. When the enclave aborts, the panic message and location are
+//!   printed to standard error, but runners don't get them as part of the
+//!   exit.
+//! * Coverage builds (`-Z profile`) produce no coverage data, since the
+//!   profiler runtime writes its counters to files at exit.
+//! * The heap has the fixed size chosen when the enclave is built. Growing it
+//!   on SGX2 hardware needs the runner to add pages for the enclave to
+//!   accept.
+//! * A thread blocked in a usercall, such as reading from a stream, can't be
+//!   interrupted.
+//! * Only the `wait` usercall takes a timeout. Read timeouts and
+//!   `TcpStream::connect_timeout` are emulated with helper threads, which use
+//!   up a TCS until the usercall returns. Write timeouts are not supported.
+//! * The enclave can't ask which usercalls the runner implements, so it
+//!   can't avoid usercalls that an older runner doesn't know.
+//! * There is no way to wait on several streams at once, each blocking
+//!   stream operation needs its own thread.
+//! * `process::exit` can only tell the runner whether the exit code was
+//!   zero. A non-zero code is printed to standard error.
+//! * Runners can't send control messages to the enclave other than over a
+//!   stream the enclave connects or accepts.
+//! * Stream data always goes through `read` and `write` usercalls, there are
+//!   no shared-memory ring buffers that userspace fills and drains.
+//! * Only reads from a `TcpStream` can be non-blocking. They are emulated
+//!   with a helper thread, since the `read`, `write` and `accept_stream`
+//!   usercalls always block.
+//! * `UdpSocket` always fails, there are no datagram usercalls.
+//! * Socket options such as `TCP_NODELAY`, the TTL and linger can't be set
+//!   or queried, the runner's defaults apply.
+//! * Host names can't be resolved inside the enclave. They are passed to the
+//!   runner as strings when connecting or binding, and `lookup_host` fails.
+//! * `TcpStream::shutdown` only takes effect inside the enclave. The peer
+//!   doesn't see the end of the stream until the last clone is dropped.
+//! * Accepted streams only carry the local and peer address strings the
+//!   runner reports, no other metadata such as the original destination of
+//!   a proxied connection.
+//! * TCP keepalive can't be configured.
+//! * There is no readiness-based selector for event loops such as mio.
+//! * The local address of an outgoing connection can't be chosen.
+//! * Connecting to a host name makes a single attempt to the address the
+//!   runner picks. Only addresses resolved inside the enclave are tried in
+//!   turn.
+//! * Enclaves behind a runner that terminates TLS can't see whether TLS was
+//!   used, the negotiated ALPN protocol or the client certificate.
+//! * The buffer sizes userspace uses for a stream can't be hinted.
 
 #![deny(missing_docs, missing_debug_implementations)]
 #![unstable(feature = "sgx_platform", issue = "56975")]
@@ -19,6 +78,24 @@ pub mod usercalls {
         pub use crate::sys::abi::usercalls::alloc::*;
     }
 
+    /// Submitting usercalls through queues in shared memory, without an
+    /// enclave exit for every usercall.
+    pub mod async_queues {
+        pub use crate::sys::abi::usercalls::async_queues::*;
+    }
+
+    /// Answering usercalls in place of userspace, to test error handling.
+    #[cfg(feature = "sgx-usercall-faults")]
+    pub mod fault {
+        pub use crate::sys::abi::usercalls::fault::*;
+    }
+
+    /// Hooks called before and after every usercall.
+    #[cfg(feature = "sgx-usercall-trace")]
+    pub mod trace {
+        pub use crate::sys::abi::usercalls::trace::*;
+    }
+
     /// Lowest-level interfaces to usercalls and usercall ABI type definitions.
     pub mod raw {
         pub use crate::sys::abi::usercalls::raw::{do_usercall, Usercalls as UsercallNrs};
@@ -37,12 +114,29 @@ pub mod usercalls {
     }
 }
 
-/// Functions for querying mapping information for pointers.
+/// Functions for querying mapping information for pointers, and heap usage.
 pub mod mem {
     pub use crate::sys::abi::mem::*;
+    pub use crate::sys::alloc::{HeapStats, heap_stats, set_zero_on_free};
+    pub use crate::sys::alloc::{MAX_WATERMARKS, register_heap_watermark};
+    #[cfg(feature = "sgx-alloc-hooks")]
+    pub use crate::sys::alloc::{AllocHooks, set_alloc_hooks};
 }
 
-pub use crate::sys::ext::{io, arch, ffi};
+pub use crate::sys::ext::{abort, io, arch, error_output, event, exceptions, ffi, futex, net,
+                          unwind};
+#[cfg(feature = "backtrace")]
+pub use crate::sys::ext::backtrace;
+
+/// Lock contention statistics and sampling. Lock statistics are only
+/// collected if the standard library is built with the `sgx-lock-stats`
+/// feature.
+pub mod perf {
+    pub use crate::sys::ext::perf::{LockStats, barrier, condvar, futex, mutex, reentrant_mutex,
+                                    reset, rwlock};
+    pub use crate::sys::ext::perf::{Sample, start_sampling, stop_sampling, take_samples};
+    pub use crate::sys::ext::perf::{UsercallStats, usercall_stats};
+}
 
 /// Functions for querying thread-related information.
 pub mod thread {
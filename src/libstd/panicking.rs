@@ -447,6 +447,9 @@ fn rust_panic_with_hook(payload: &mut dyn BoxMeUp,
 
     let panics = update_panic_count(1);
 
+    #[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+    crate::sys::abort::panicking(panics, message, payload, file_line_col);
+
     // If this is the third nested call (e.g., panics == 2, this is 0-indexed),
     // the panic hook probably triggered the last panic, otherwise the
     // double-panic check would have aborted the process. In this case abort the
@@ -455,7 +458,7 @@ fn rust_panic_with_hook(payload: &mut dyn BoxMeUp,
     if panics > 2 {
         util::dumb_print(format_args!("thread panicked while processing \
                                        panic. aborting.\n"));
-        unsafe { intrinsics::abort() }
+        abort_double_panic()
     }
 
     unsafe {
@@ -488,12 +491,20 @@ fn rust_panic_with_hook(payload: &mut dyn BoxMeUp,
         // unwinding or otherwise exiting the thread cleanly.
         util::dumb_print(format_args!("thread panicked while panicking. \
                                        aborting.\n"));
-        unsafe { intrinsics::abort() }
+        abort_double_panic()
     }
 
     rust_panic(payload)
 }
 
+// Aborts the process after a thread panicked while it was already panicking.
+fn abort_double_panic() -> ! {
+    #[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+    crate::sys::abort::abort_with_code(crate::sys::abort::AbortReason::DoublePanic);
+    #[cfg(not(all(target_vendor = "fortanix", target_env = "sgx")))]
+    unsafe { intrinsics::abort(); }
+}
+
 /// Shim around rust_panic. Called by resume_unwind.
 pub fn update_count_then_panic(msg: Box<dyn Any + Send>) -> ! {
     update_panic_count(1);
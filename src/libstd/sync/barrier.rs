@@ -1,5 +1,5 @@
 use crate::fmt;
-use crate::sync::{Mutex, Condvar};
+use crate::sys_common::barrier as sys;
 
 /// A barrier enables multiple threads to synchronize the beginning
 /// of some computation.
@@ -29,15 +29,7 @@ use crate::sync::{Mutex, Condvar};
 /// ```
 #[stable(feature = "rust1", since = "1.0.0")]
 pub struct Barrier {
-    lock: Mutex<BarrierState>,
-    cvar: Condvar,
-    num_threads: usize,
-}
-
-// The inner state of a double barrier
-struct BarrierState {
-    count: usize,
-    generation_id: usize,
+    inner: sys::Barrier,
 }
 
 /// A `BarrierWaitResult` is returned by [`wait`] when all threads in the [`Barrier`]
@@ -81,14 +73,7 @@ impl Barrier {
     /// ```
     #[stable(feature = "rust1", since = "1.0.0")]
     pub fn new(n: usize) -> Barrier {
-        Barrier {
-            lock: Mutex::new(BarrierState {
-                count: 0,
-                generation_id: 0,
-            }),
-            cvar: Condvar::new(),
-            num_threads: n,
-        }
+        Barrier { inner: sys::Barrier::new(n) }
     }
 
     /// Blocks the current thread until all threads have rendezvoused here.
@@ -129,23 +114,7 @@ impl Barrier {
     /// ```
     #[stable(feature = "rust1", since = "1.0.0")]
     pub fn wait(&self) -> BarrierWaitResult {
-        let mut lock = self.lock.lock().unwrap();
-        let local_gen = lock.generation_id;
-        lock.count += 1;
-        if lock.count < self.num_threads {
-            // We need a while loop to guard against spurious wakeups.
-            // http://en.wikipedia.org/wiki/Spurious_wakeup
-            while local_gen == lock.generation_id &&
-                  lock.count < self.num_threads {
-                lock = self.cvar.wait(lock).unwrap();
-            }
-            BarrierWaitResult(false)
-        } else {
-            lock.count = 0;
-            lock.generation_id = lock.generation_id.wrapping_add(1);
-            self.cvar.notify_all();
-            BarrierWaitResult(true)
-        }
+        BarrierWaitResult(self.inner.wait())
     }
 }
 
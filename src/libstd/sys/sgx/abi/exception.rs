@@ -0,0 +1,459 @@
+//! Handling hardware exceptions that happen inside the enclave.
+//!
+//! When an exception happens in the enclave, the CPU saves the state of the
+//! interrupted thread in the current SSA frame of its TCS and exits the
+//! enclave. Userspace then enters the enclave on the same TCS again, which
+//! uses the next SSA frame, so the enclave can look at the exception, and
+//! resumes the interrupted thread with ERESUME once the enclave exits. This
+//! needs TCSs with two SSA frames, and the post-linker to fill in
+//! `SSA_FRAME_SIZE`, `STACK_SIZE`, `SSA_EXINFO` and the SSA base in the
+//! TCS-local storage. Otherwise, the enclave aborts on any exception.
+//!
+//! `sgx_entry` calls `handle_exception` with the GPR area of the SSA frame,
+//! on the interrupted thread's stack below its red zone, or on a stack shared
+//! by all threads if there isn't enough space left. #UD, #GP and #PF in Rust
+//! code are first passed to the handler installed with
+//! `ext::exceptions::set_handler`, if any, which can resume the thread.
+//!
+//! Otherwise, exceptions that Rust code can cause deliberately, and that
+//! userspace can't make happen at arbitrary instructions, are turned into
+//! panics: the saved state is changed so that the thread resumes in
+//! `exception_trampoline`, as if the faulting instruction had called it,
+//! which calls `exception_panic`. All other exceptions abort the enclave,
+//! since unwinding from an arbitrary instruction could leave data structures
+//! in an inconsistent state that destructors then operate on. Page faults,
+//! for example, can be caused by the OS at any memory access.
+//!
+//! A panic unwinds through the faulting function like through one that
+//! called `panic!`, except that functions with cleanups can't be unwound from
+//! an instruction that isn't a call. The enclave aborts in that case, after
+//! the panic message, which includes the exception, was printed.
+
+use crate::cmp;
+use crate::fmt;
+use crate::slice;
+
+use super::mem::image_base;
+use super::panic;
+use super::tls::Tls;
+use crate::sys::abort::{abort_with_code, print_err_fmt, AbortReason};
+use crate::sys::ext::exceptions::{self, Action, Context, Registers};
+
+/// The GPRSGX area at the end of an SSA frame.
+#[repr(C)]
+pub struct Gpr {
+    rax: u64,
+    rcx: u64,
+    rdx: u64,
+    rbx: u64,
+    rsp: u64,
+    rbp: u64,
+    rsi: u64,
+    rdi: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rflags: u64,
+    rip: u64,
+    ursp: u64,
+    urbp: u64,
+    exitinfo: u32,
+    reserved: u32,
+    fsbase: u64,
+    gsbase: u64,
+}
+
+/// The EXINFO area of an SSA frame, right below the GPR area. Only present
+/// if the enclave was built with MISCSELECT.EXINFO, and only written for
+/// page faults and general protection faults.
+#[repr(C)]
+pub struct ExInfo {
+    maddr: u64,
+    errcd: u32,
+    reserved: u32,
+}
+
+// The flags an exception handler can change: CF, PF, AF, ZF, SF, DF and OF
+const HANDLER_RFLAGS: u64 = 0xcd5;
+
+// EXITINFO fields
+const EXITINFO_VECTOR_MASK: u32 = 0xff;
+const EXITINFO_VALID: u32 = 1 << 31;
+
+// Exception vectors
+const DE: u8 = 0;
+const DB: u8 = 1;
+const BP: u8 = 3;
+const UD: u8 = 6;
+const GP: u8 = 13;
+const PF: u8 = 14;
+
+// The size of the guard area the post-linker leaves unmapped below each
+// thread's stack. With stack probes, a stack overflow faults in it before
+// any memory below is touched.
+const STACK_GUARD_SIZE: u64 = 0x1000;
+
+// `sgx_entry` leaves this much space below the interrupted stack pointer
+// for the red zone and the frame of `exception_trampoline`.
+const RED_ZONE: u64 = 128;
+
+extern "C" {
+    fn exception_trampoline();
+    static entry_code_start: u8;
+    static entry_code_end: u8;
+    static TEXT_BASE: u64;
+    static TEXT_SIZE: u64;
+    static STACK_SIZE: u64;
+    fn get_stack_top() -> u64;
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Exception {
+    // `None` if the CPU didn't report the exception
+    vector: Option<u8>,
+    // The address of the faulting instruction
+    rip: u64,
+    // The error code and faulting address of #PF and #GP, if the SSA frames
+    // have an EXINFO area
+    exinfo: Option<(u32, u64)>,
+}
+
+impl Exception {
+    fn new(gpr: &Gpr, exinfo: Option<&ExInfo>) -> Exception {
+        let vector = if gpr.exitinfo & EXITINFO_VALID != 0 {
+            Some((gpr.exitinfo & EXITINFO_VECTOR_MASK) as u8)
+        } else {
+            None
+        };
+        let exinfo = match (vector, exinfo) {
+            (Some(PF), Some(exinfo)) | (Some(GP), Some(exinfo)) => {
+                Some((exinfo.errcd, exinfo.maddr))
+            }
+            _ => None,
+        };
+        Exception { vector, rip: gpr.rip, exinfo }
+    }
+
+    // Whether the exception is caused by a stack overflow, given the bounds
+    // of the thread's stack.
+    fn is_stack_overflow(&self, rsp: u64, stack: (u64, u64), stack_exhausted: bool) -> bool {
+        let (bottom, top) = stack;
+        if stack_exhausted && rsp <= top {
+            return true;
+        }
+        match (self.vector, self.exinfo) {
+            (Some(PF), Some((_, maddr))) => {
+                maddr < bottom && maddr >= bottom.wrapping_sub(STACK_GUARD_SIZE)
+            }
+            _ => false,
+        }
+    }
+
+    // Whether the exception is turned into a panic. `ud2`, which is how
+    // `intrinsics::abort` aborts, is a #UD that must abort.
+    fn is_panic(&self, instruction: &[u8]) -> bool {
+        match self.vector {
+            Some(DE) | Some(DB) | Some(BP) => true,
+            Some(UD) => !instruction.starts_with(&[0x0f, 0x0b]),
+            _ => false,
+        }
+    }
+}
+
+// The name and mnemonic of the exceptions that can be reported in an enclave
+fn vector_name(vector: u8) -> Option<(&'static str, &'static str)> {
+    match vector {
+        0 => Some(("divide error", "#DE")),
+        1 => Some(("debug exception", "#DB")),
+        3 => Some(("breakpoint", "#BP")),
+        5 => Some(("bound range exceeded", "#BR")),
+        6 => Some(("invalid opcode", "#UD")),
+        13 => Some(("general protection fault", "#GP")),
+        14 => Some(("page fault", "#PF")),
+        16 => Some(("x87 floating-point exception", "#MF")),
+        17 => Some(("alignment check", "#AC")),
+        19 => Some(("SIMD floating-point exception", "#XM")),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.vector {
+            Some(vector) => match vector_name(vector) {
+                Some((name, mnemonic)) => write!(f, "{} ({}, vector {})", name, mnemonic, vector)?,
+                None => write!(f, "hardware exception (vector {})", vector)?,
+            },
+            None => f.write_str("hardware exception that was not reported")?,
+        }
+        write!(f, " at image base + {:#x}", self.rip.wrapping_sub(image_base()))?;
+        if let Some((errcd, maddr)) = self.exinfo {
+            write!(f, ", error code {:#x}", errcd)?;
+            if self.vector == Some(PF) {
+                write!(f, ", accessing {:#x}", maddr)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The number of bytes of the enclave text from `rip` on, if `rip` lies in
+// the text outside of the entry code.
+fn text_from(rip: u64) -> Option<u64> {
+    // unsafe ok: link-time constants
+    let (start, end, entry_start, entry_end) = unsafe {
+        (image_base() + TEXT_BASE,
+         image_base() + TEXT_BASE + TEXT_SIZE,
+         &entry_code_start as *const u8 as u64,
+         &entry_code_end as *const u8 as u64)
+    };
+    if rip < start || rip >= end || (rip >= entry_start && rip < entry_end) {
+        return None;
+    }
+    Some(end - rip)
+}
+
+// The bounds of the current thread's stack
+fn stack_bounds() -> (u64, u64) {
+    // unsafe ok: the stack top is set up before any code runs, and the stack
+    // size is a link-time constant
+    unsafe {
+        let top = get_stack_top();
+        (top - STACK_SIZE, top)
+    }
+}
+
+impl Gpr {
+    fn registers(&self) -> Registers {
+        Registers {
+            rax: self.rax,
+            rcx: self.rcx,
+            rdx: self.rdx,
+            rbx: self.rbx,
+            rsp: self.rsp,
+            rbp: self.rbp,
+            rsi: self.rsi,
+            rdi: self.rdi,
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rflags: self.rflags,
+            rip: self.rip,
+        }
+    }
+
+    fn set_registers(&mut self, registers: &Registers) {
+        self.rax = registers.rax;
+        self.rcx = registers.rcx;
+        self.rdx = registers.rdx;
+        self.rbx = registers.rbx;
+        self.rsp = registers.rsp;
+        self.rbp = registers.rbp;
+        self.rsi = registers.rsi;
+        self.rdi = registers.rdi;
+        self.r8 = registers.r8;
+        self.r9 = registers.r9;
+        self.r10 = registers.r10;
+        self.r11 = registers.r11;
+        self.r12 = registers.r12;
+        self.r13 = registers.r13;
+        self.r14 = registers.r14;
+        self.r15 = registers.r15;
+        self.rflags = (self.rflags & !HANDLER_RFLAGS) | (registers.rflags & HANDLER_RFLAGS);
+        self.rip = registers.rip;
+    }
+}
+
+// Passes the exception to the handler installed with
+// `ext::exceptions::set_handler`, if any. Returns whether the thread can be
+// resumed.
+fn call_handler(gpr: &mut Gpr, exception: &Exception) -> bool {
+    let kind = match exception.vector {
+        Some(UD) => exceptions::Exception::InvalidOpcode,
+        Some(GP) => exceptions::Exception::GeneralProtection,
+        Some(PF) => exceptions::Exception::PageFault,
+        _ => return false,
+    };
+    let handler = match exceptions::handler() {
+        Some(handler) => handler,
+        None => return false,
+    };
+    let (error_code, address) = match (exception.vector, exception.exinfo) {
+        (Some(PF), Some((errcd, maddr))) => (Some(errcd), Some(maddr)),
+        (_, Some((errcd, _))) => (Some(errcd), None),
+        (_, None) => (None, None),
+    };
+    let mut context = Context::new(kind, error_code, address, gpr.registers());
+    match handler(&mut context) {
+        Action::Resume => {
+            gpr.set_registers(&context.into_registers());
+            true
+        }
+        Action::Skip(len) => {
+            let mut registers = context.into_registers();
+            registers.rip = registers.rip.wrapping_add(len);
+            gpr.set_registers(&registers);
+            true
+        }
+        Action::Abort => fatal(exception, "was not handled by the exception handler"),
+        Action::Unhandled => false,
+    }
+}
+
+fn abort(args: fmt::Arguments<'_>, reason: AbortReason) -> ! {
+    if !Tls::is_active() {
+        // Printing needs thread-local storage
+        panic::panic_msg("hardware exception on a thread without thread-local storage");
+    }
+    print_err_fmt(args);
+    abort_with_code(reason)
+}
+
+fn stack_overflow(exception: &Exception) -> ! {
+    abort(format_args!("thread stack overflow: {}\n", exception), AbortReason::StackOverflow)
+}
+
+fn fatal(exception: &Exception, reason: &str) -> ! {
+    abort(format_args!("{} {}\n", exception, reason), AbortReason::HardwareException)
+}
+
+/// Called by `sgx_entry` when the enclave is entered to handle an exception.
+/// `stack_exhausted` is set if there wasn't enough space on the interrupted
+/// thread's stack, and the handler runs on a stack shared by all threads.
+/// That stack is never released, so the handler must not return then.
+///
+/// Returns once the interrupted thread can be resumed.
+#[cfg_attr(not(test), no_mangle)]
+#[unwind(aborts)]
+pub unsafe extern "C" fn handle_exception(gpr: &mut Gpr, exinfo: Option<&ExInfo>,
+                                          stack_exhausted: bool) {
+    let exception = Exception::new(gpr, exinfo);
+    if exception.is_stack_overflow(gpr.rsp, stack_bounds(), stack_exhausted) {
+        stack_overflow(&exception);
+    }
+    if stack_exhausted {
+        fatal(&exception, "with no stack space left to handle it");
+    }
+    let text_len = match text_from(gpr.rip) {
+        Some(len) if Tls::is_active() => len,
+        _ => fatal(&exception, "outside of Rust code"),
+    };
+    if call_handler(gpr, &exception) {
+        return;
+    }
+    let instruction = slice::from_raw_parts(gpr.rip as *const u8, cmp::min(text_len, 2) as usize);
+    if !exception.is_panic(instruction) {
+        fatal(&exception, "can't be recovered from");
+    }
+    // Resume in `exception_trampoline` as if the faulting instruction had
+    // called it, with the faulting instruction and stack pointer where the
+    // trampoline's unwind information expects them.
+    let frame = ((gpr.rsp - RED_ZONE - 16) & !0xf) as *mut u64;
+    *frame = gpr.rip;
+    *frame.add(1) = gpr.rsp;
+    gpr.rsp = frame as u64;
+    gpr.rip = exception_trampoline as usize as u64;
+    gpr.rdi = exception.vector.unwrap_or(0) as u64;
+    gpr.rsi = exception.rip;
+}
+
+/// Called by `exception_trampoline` on the thread that caused an exception.
+#[cfg_attr(not(test), no_mangle)]
+#[unwind(allowed)]
+pub extern "C" fn exception_panic(vector: u64, rip: u64) -> ! {
+    let exception = Exception { vector: Some(vector as u8), rip, exinfo: None };
+    panic!("{}", exception)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem;
+
+    #[test]
+    fn layout() {
+        // Must match `ssa_gpr_size` and `ssa_gpr_rsp` in entry.S
+        assert_eq!(mem::size_of::<Gpr>(), 184);
+        let gpr: Gpr = unsafe { mem::zeroed() };
+        assert_eq!(&gpr.rsp as *const u64 as usize - &gpr as *const Gpr as usize, 32);
+        assert_eq!(mem::size_of::<ExInfo>(), 16);
+    }
+
+    #[test]
+    fn handler_registers() {
+        let mut gpr: Gpr = unsafe { mem::zeroed() };
+        gpr.rflags = 0x202; // IF
+        gpr.rip = 0x1000;
+        let mut registers = gpr.registers();
+        registers.rax = 1;
+        registers.r15 = 2;
+        registers.rflags = 0x100 | 0x41; // TF, ZF, CF
+        registers.rip += 2;
+        gpr.set_registers(&registers);
+        assert_eq!(gpr.rax, 1);
+        assert_eq!(gpr.r15, 2);
+        assert_eq!(gpr.rflags, 0x243);
+        assert_eq!(gpr.rip, 0x1002);
+    }
+
+    #[test]
+    fn panics() {
+        let exception = |vector| Exception { vector, rip: 0, exinfo: None };
+        assert!(exception(Some(UD)).is_panic(&[0x0f, 0xa2])); // cpuid
+        assert!(!exception(Some(UD)).is_panic(&[0x0f, 0x0b])); // ud2
+        assert!(exception(Some(DE)).is_panic(&[0xf7, 0xf1]));
+        assert!(exception(Some(BP)).is_panic(&[0xcc, 0x90]));
+        assert!(!exception(Some(PF)).is_panic(&[0x8b, 0x00]));
+        assert!(!exception(Some(GP)).is_panic(&[0x8b, 0x00]));
+        assert!(!exception(None).is_panic(&[0x90, 0x90]));
+    }
+
+    #[test]
+    fn stack_overflows() {
+        let stack = (0x10_0000, 0x20_0000);
+        let page_fault = |maddr| Exception {
+            vector: Some(PF),
+            rip: 0,
+            exinfo: Some((6, maddr)),
+        };
+        assert!(page_fault(0xf_fff8).is_stack_overflow(0xf_fff8, stack, false));
+        assert!(page_fault(0xf_f000).is_stack_overflow(0x10_0000, stack, false));
+        assert!(!page_fault(0xf_efff).is_stack_overflow(0x10_0000, stack, false));
+        assert!(!page_fault(0x10_0000).is_stack_overflow(0x10_0000, stack, false));
+        let ud = Exception { vector: Some(UD), rip: 0, exinfo: None };
+        assert!(ud.is_stack_overflow(0x10_0100, stack, true));
+        assert!(!ud.is_stack_overflow(0x10_0100, stack, false));
+        // RSP isn't on the stack at all
+        assert!(!ud.is_stack_overflow(0x30_0000, stack, true));
+    }
+
+    #[test]
+    fn display() {
+        let base = image_base();
+        let exception = Exception { vector: Some(UD), rip: base + 0x1234, exinfo: None };
+        assert_eq!(exception.to_string(), "invalid opcode (#UD, vector 6) at image base + 0x1234");
+        let exception = Exception {
+            vector: Some(PF),
+            rip: base + 0x10,
+            exinfo: Some((6, 0xdead000)),
+        };
+        assert_eq!(exception.to_string(),
+                   "page fault (#PF, vector 14) at image base + 0x10, error code 0x6, \
+                    accessing 0xdead000");
+        let exception = Exception { vector: Some(7), rip: base, exinfo: None };
+        assert_eq!(exception.to_string(), "hardware exception (vector 7) at image base + 0x0");
+        let exception = Exception { vector: None, rip: base, exinfo: None };
+        assert_eq!(exception.to_string(),
+                   "hardware exception that was not reported at image base + 0x0");
+    }
+}
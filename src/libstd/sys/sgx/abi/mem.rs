@@ -1,3 +1,5 @@
+use crate::fmt;
+
 // Do not remove inline: will result in relocation failure
 #[inline(always)]
 pub(crate) unsafe fn rel_ptr<T>(offset: u64) -> *const T {
@@ -30,7 +32,10 @@ pub fn image_base() -> u64 {
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn is_enclave_range(p: *const u8, len: usize) -> bool {
     let start=p as u64;
-    let end=start + (len as u64);
+    let end = match start.checked_add(len as u64) {
+        Some(end) => end,
+        None => return false,
+    };
     start >= image_base() &&
         end <= image_base() + (unsafe { ENCLAVE_SIZE } as u64) // unsafe ok: link-time constant
 }
@@ -39,7 +44,64 @@ pub fn is_enclave_range(p: *const u8, len: usize) -> bool {
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn is_user_range(p: *const u8, len: usize) -> bool {
     let start=p as u64;
-    let end=start + (len as u64);
+    let end = match start.checked_add(len as u64) {
+        Some(end) => end,
+        None => return false,
+    };
     end <= image_base() ||
         start >= image_base() + (unsafe { ENCLAVE_SIZE } as u64) // unsafe ok: link-time constant
 }
+
+/// The reason a memory range was rejected by [`validate_user_range`].
+///
+/// [`validate_user_range`]: fn.validate_user_range.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub enum UserRangeError {
+    /// The pointer is null.
+    Null,
+    /// The pointer is not aligned.
+    Unaligned,
+    /// The range wraps around the end of the address space.
+    Overflow,
+    /// The range overlaps with enclave memory.
+    NotUserMemory,
+    /// The requested alignment is not a power of two.
+    InvalidAlignment,
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Display for UserRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            UserRangeError::Null => "pointer to user memory is null",
+            UserRangeError::Unaligned => "pointer to user memory is not aligned",
+            UserRangeError::Overflow => "user memory range wraps around the address space",
+            UserRangeError::NotUserMemory => "user memory range overlaps with the enclave",
+            UserRangeError::InvalidAlignment => "alignment is not a power of two",
+        })
+    }
+}
+
+/// Checks that the `len` bytes at `p` can hold values aligned to `align` in
+/// user memory.
+///
+/// All pointers received from userspace must be checked like this before
+/// they are used. `align` must be a power of two, otherwise
+/// `UserRangeError::InvalidAlignment` is returned.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn validate_user_range(p: *const u8, len: usize, align: usize) -> Result<(), UserRangeError> {
+    if !align.is_power_of_two() {
+        Err(UserRangeError::InvalidAlignment)
+    } else if p.is_null() {
+        Err(UserRangeError::Null)
+    } else if (p as usize) & (align - 1) != 0 {
+        Err(UserRangeError::Unaligned)
+    } else if (p as usize).checked_add(len).is_none() {
+        Err(UserRangeError::Overflow)
+    } else if !is_user_range(p, len) {
+        Err(UserRangeError::NotUserMemory)
+    } else {
+        Ok(())
+    }
+}
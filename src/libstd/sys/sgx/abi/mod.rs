@@ -4,6 +4,7 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::io::Write;
 
 // runtime features
+mod exception;
 mod reloc;
 pub(super) mod panic;
 
@@ -19,6 +20,7 @@ global_asm!(include_str!("entry.S"));
 
 #[cfg(not(test))]
 #[no_mangle]
+#[unwind(aborts)]
 unsafe extern "C" fn tcs_init(secondary: bool) {
     // Be very careful when changing this code: it runs before the binary has been
     // relocated. Any indirect accesses to symbols will likely fail.
@@ -54,41 +56,83 @@ unsafe extern "C" fn tcs_init(secondary: bool) {
 // able to specify this
 #[cfg(not(test))]
 #[no_mangle]
+#[unwind(aborts)]
 extern "C" fn entry(p1: u64, p2: u64, p3: u64, secondary: bool, p4: u64, p5: u64) -> (u64, u64) {
-    // FIXME: how to support TLS in library mode?
-    let tls = Box::new(tls::Tls::new());
-    let _tls_guard = unsafe { tls.activate() };
-
     if secondary {
-        super::thread::Thread::entry();
+        // Threads spawned later may reuse this TCS, see `Thread::new`. Each
+        // of them gets its own thread-local storage.
+        let mut idle = false;
+        while with_tls(|| super::thread::Thread::entry(idle)) {
+            idle = true;
+        }
 
         (0, 0)
     } else {
-        extern "C" {
-            fn main(argc: isize, argv: *const *const u8) -> isize;
-        }
+        with_tls(|| main_entry(p1, p2, p3, p4, p5))
+    }
+}
+
+#[cfg(not(test))]
+fn main_entry(p1: u64, p2: u64, p3: u64, p4: u64, p5: u64) -> ! {
+    extern "C" {
+        fn main(argc: isize, argv: *const *const u8) -> isize;
+    }
+
+    // check entry is being called according to ABI
+    rtassert!(p3 == 0);
+    rtassert!(p4 == 0);
+    rtassert!(p5 == 0);
+
+    unsafe {
+        // The actual types of these arguments are `p1: *const Arg, p2:
+        // usize`. We can't currently customize the argument list of Rust's
+        // main function, so we pass these in as the standard pointer-sized
+        // values in `argc` and `argv`.
+        let ret = main(p2 as _, p1 as _);
+        exit_with_code(ret)
+    }
+}
+
+// Runs `f` with fresh thread-local storage.
+#[cfg(not(test))]
+fn with_tls<R, F: FnOnce() -> R>(f: F) -> R {
+    // FIXME: how to support TLS in library mode?
+    let tls = Box::new(tls::Tls::new());
+    let _tls_guard = unsafe { tls.activate() };
+    // Dropped before the TLS guard, while the panic count is still available
+    let _unwind_guard = AbortOnUnwind;
+    f()
+}
+
+// Unwinding must never leave the enclave, userspace frames can't be trusted.
+// `#[unwind(aborts)]` on the entry points already guarantees that, this guard
+// additionally explains why the enclave aborted.
+struct AbortOnUnwind;
 
-        // check entry is being called according to ABI
-        rtassert!(p3 == 0);
-        rtassert!(p4 == 0);
-        rtassert!(p5 == 0);
-
-        unsafe {
-            // The actual types of these arguments are `p1: *const Arg, p2:
-            // usize`. We can't currently customize the argument list of Rust's
-            // main function, so we pass these in as the standard pointer-sized
-            // values in `argc` and `argv`.
-            let ret = main(p2 as _, p1 as _);
-            exit_with_code(ret)
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        if crate::thread::panicking() {
+            rtabort!("a panic unwound to the enclave entry point");
         }
     }
 }
 
+// FIXME: Builds with `-Z profile` can't produce coverage data for this target:
+// the profiler runtime writes its counters to files at exit, and there is no
+// file I/O inside the enclave. Flushing them here would need the profiler
+// runtime to be built for the target, and a way to ship the counters to the
+// runner, either a dedicated usercall or a framed stream on standard error.
 pub(super) fn exit_with_code(code: isize) -> ! {
     if code != 0 {
         if let Some(mut out) = panic::SgxPanicOutput::new() {
             let _ = write!(out, "Exited with status code {}", code);
         }
+        crate::sys::abort::print_err_fmt(format_args!("enclave exited with code {}\n", code));
     }
+    // FIXME: The runner only learns whether `code` was zero, the code itself
+    // only shows up in the debug panic buffer and on standard error. Passing
+    // the code, a message and a backtrace needs a variant of the `exit`
+    // usercall that takes a status structure in user memory, which the
+    // usercall ABI doesn't have.
     usercalls::exit(code != 0);
 }
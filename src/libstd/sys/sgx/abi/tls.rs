@@ -76,6 +76,7 @@ impl<'a> Drop for ActiveTls<'a> {
                 }
             }
         }
+        unsafe { set_tls_ptr(ptr::null()) };
     }
 }
 
@@ -95,6 +96,11 @@ impl Tls {
         mem::forget(self);
     }
 
+    /// Returns whether TLS is available on the current thread.
+    pub fn is_active() -> bool {
+        unsafe { !get_tls_ptr().is_null() }
+    }
+
     unsafe fn current<'a>() -> &'a Tls {
         &*(get_tls_ptr() as *const Tls)
     }
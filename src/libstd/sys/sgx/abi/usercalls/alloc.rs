@@ -1,14 +1,77 @@
 #![allow(unused)]
 
 use crate::ptr::{self, NonNull};
-use crate::mem;
+use crate::mem::{self, MaybeUninit};
 use crate::cell::UnsafeCell;
+use crate::cmp;
+use crate::fmt;
 use crate::slice;
+use crate::sync::Mutex;
 use crate::ops::{Deref, DerefMut, Index, IndexMut, CoerceUnsized};
 use crate::slice::SliceIndex;
 
+#[cfg(feature = "sgx-lvi-fence")]
+use core::arch::x86_64::_mm_lfence;
+
 use fortanix_sgx_abi::*;
-use super::super::mem::is_user_range;
+use super::super::mem::validate_user_range;
+
+// With the `sgx-lvi-fence` feature, every load of values controlled by
+// userspace is followed by a load fence, so that code doesn't speculatively
+// run with values injected using Load Value Injection.
+#[inline(always)]
+pub(super) fn load_fence() {
+    #[cfg(feature = "sgx-lvi-fence")]
+    unsafe { _mm_lfence() }
+}
+
+// Copies `len` bytes from `src` to `dst` with string instructions. The
+// compiler can't merge, split, repeat or reorder the accesses, as it might
+// for `memcpy`, so every byte is read and written exactly once.
+//
+// Bytes are copied one at a time until `dst` is 8-byte aligned, then 8 bytes
+// at a time, then the remaining tail one at a time.
+#[inline(always)]
+unsafe fn rep_movs(mut src: *const u8, mut dst: *mut u8, len: usize) {
+    let head = cmp::min(dst.align_offset(8), len);
+    let mut count = head;
+    asm!("rep movsb"
+         : "+{rsi}"(src), "+{rdi}"(dst), "+{rcx}"(count)
+         :
+         : "memory"
+         : "volatile");
+    let mut count = (len - head) / 8;
+    asm!("rep movsq"
+         : "+{rsi}"(src), "+{rdi}"(dst), "+{rcx}"(count)
+         :
+         : "memory"
+         : "volatile");
+    let mut count = (len - head) % 8;
+    asm!("rep movsb"
+         : "+{rsi}"(src), "+{rdi}"(dst), "+{rcx}"(count)
+         :
+         : "memory"
+         : "volatile");
+}
+
+/// Copies `len` bytes from `src` in enclave memory to `dst` in user memory.
+///
+/// # Safety
+/// The caller must ensure `src` and `dst` are valid for `len` bytes.
+unsafe fn copy_to_userspace(src: *const u8, dst: *mut u8, len: usize) {
+    rep_movs(src, dst, len)
+}
+
+/// Copies `len` bytes from `src` in user memory to `dst` in enclave memory.
+/// Every byte is read exactly once, so the copy can be validated without
+/// userspace being able to change it.
+///
+/// # Safety
+/// The caller must ensure `src` and `dst` are valid for `len` bytes.
+unsafe fn copy_from_userspace(src: *const u8, dst: *mut u8, len: usize) {
+    rep_movs(src, dst, len);
+    load_fence();
+}
 
 /// A type that can be safely read from or written to userspace.
 ///
@@ -39,11 +102,71 @@ use super::super::mem::is_user_range;
 /// * **No internal padding.** Padding might contain previously-initialized
 ///   secret data stored at that memory location and must not be leaked for
 ///   confidentiality reasons.
+///
+/// # Implementing `UserSafeSized`
+///
+/// This trait is implemented for integers and for arrays of up to 32 elements
+/// of a `UserSafeSized` type. A `#[repr(C)]` struct can implement it if all of
+/// its fields are `UserSafeSized` and it has no padding. Use
+/// [`impl_user_safe_sized!`] rather than implementing it by hand, which checks
+/// both at compile time:
+///
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Copy, Clone)]
+/// struct Request {
+///     id: u64,
+///     len: u32,
+///     flags: u32,
+/// }
+///
+/// impl_user_safe_sized!(Request { id: u64, len: u32, flags: u32 });
+///
+/// let mut request = User::<Request>::uninitialized();
+/// request.copy_from_enclave(&Request { id: 1, len: 0, flags: 0 });
+/// ```
+///
+/// [`impl_user_safe_sized!`]: macro.impl_user_safe_sized.html
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub unsafe trait UserSafeSized: Copy + Sized {}
 
+fn assert_user_safe_sized<T: UserSafeSized>() {}
+
+/// Implements [`UserSafeSized`] for a struct, given all of its fields and
+/// their types.
+///
+/// Compilation fails unless the fields are exactly the fields of the struct,
+/// every field is `UserSafeSized`, and the sizes of the fields add up to the
+/// size of the struct, so that there is no padding. The struct should be
+/// `#[repr(C)]` for userspace to agree on its layout. Generic structs aren't
+/// supported.
+///
+/// [`UserSafeSized`]: trait.UserSafeSized.html
 #[unstable(feature = "sgx_platform", issue = "56975")]
-unsafe impl UserSafeSized for u8 {}
+pub macro impl_user_safe_sized($name:ident { $($field:ident: $t:ty),* $(,)* }) {
+    unsafe impl UserSafeSized for $name {}
+
+    // Never called, it only needs to type check
+    #[allow(dead_code)]
+    fn check(value: $name) {
+        let $name { $($field),* } = value;
+        $(
+            let _: $t = $field;
+            assert_user_safe_sized::<$t>();
+        )*
+        let _: [(); mem::size_of::<$name>()] = [(); 0 $(+ mem::size_of::<$t>())*];
+    }
+}
+
+macro_rules! user_safe_ints {
+    ($($t:ident)*) => {$(
+        #[unstable(feature = "sgx_platform", issue = "56975")]
+        unsafe impl UserSafeSized for $t {}
+    )*}
+}
+
+user_safe_ints! { u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize }
+
 #[unstable(feature = "sgx_platform", issue = "56975")]
 unsafe impl<T> UserSafeSized for FifoDescriptor<T> {}
 #[unstable(feature = "sgx_platform", issue = "56975")]
@@ -52,8 +175,18 @@ unsafe impl UserSafeSized for ByteBuffer {}
 unsafe impl UserSafeSized for Usercall {}
 #[unstable(feature = "sgx_platform", issue = "56975")]
 unsafe impl UserSafeSized for Return {}
-#[unstable(feature = "sgx_platform", issue = "56975")]
-unsafe impl<T: UserSafeSized> UserSafeSized for [T; 2] {}
+
+// Array elements are never separated by padding
+macro_rules! user_safe_arrays {
+    ($($n:tt)*) => {$(
+        #[unstable(feature = "sgx_platform", issue = "56975")]
+        unsafe impl<T: UserSafeSized> UserSafeSized for [T; $n] {}
+    )*}
+}
+
+user_safe_arrays! {
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32
+}
 
 /// A type that can be represented in memory as one or more `UserSafeSized`s.
 #[unstable(feature = "sgx_platform", issue = "56975")]
@@ -107,13 +240,9 @@ pub unsafe trait UserSafe {
     /// * the pointer is null.
     /// * the pointed-to range is not in user memory.
     unsafe fn check_ptr(ptr: *const Self) {
-        let is_aligned = |p| -> bool {
-            0 == (p as usize) & (Self::align_of() - 1)
-        };
-
-        assert!(is_aligned(ptr as *const u8));
-        assert!(is_user_range(ptr as _, mem::size_of_val(&*ptr)));
-        assert!(!ptr.is_null());
+        if let Err(err) = validate_user_range(ptr as _, mem::size_of_val(&*ptr), Self::align_of()) {
+            panic!("{}", err);
+        }
     }
 }
 
@@ -194,11 +323,8 @@ impl<T: ?Sized> User<T> where T: UserSafe {
             } else {
                 T::align_of() as _ // dangling pointer ok for size 0
             };
-            if let Ok(v) = crate::panic::catch_unwind(|| T::from_raw_sized(ptr, size)) {
-                User(NonNull::new_userref(v))
-            } else {
-                rtabort!("Got invalid pointer from alloc() usercall")
-            }
+            // `alloc` checked that the pointer is in user memory
+            User(NonNull::new_userref(T::from_raw_sized(ptr, size)))
         }
     }
 
@@ -206,7 +332,7 @@ impl<T: ?Sized> User<T> where T: UserSafe {
     pub fn new_from_enclave(val: &T) -> Self {
         unsafe {
             let ret = Self::new_uninit_bytes(mem::size_of_val(val));
-            ptr::copy(
+            copy_to_userspace(
                 val as *const T as *const u8,
                 ret.0.as_ptr() as *mut u8,
                 mem::size_of_val(val)
@@ -317,7 +443,7 @@ impl<T: ?Sized> UserRef<T> where T: UserSafe {
     pub fn copy_from_enclave(&mut self, val: &T) {
         unsafe {
             assert_eq!(mem::size_of_val(val), mem::size_of_val( &*self.0.get() ));
-            ptr::copy(
+            copy_to_userspace(
                 val as *const T as *const u8,
                 self.0.get() as *mut T as *mut u8,
                 mem::size_of_val(val)
@@ -333,7 +459,7 @@ impl<T: ?Sized> UserRef<T> where T: UserSafe {
     pub fn copy_to_enclave(&self, dest: &mut T) {
         unsafe {
             assert_eq!(mem::size_of_val(dest), mem::size_of_val( &*self.0.get() ));
-            ptr::copy(
+            copy_from_userspace(
                 self.0.get() as *const T as *const u8,
                 dest as *mut T as *mut u8,
                 mem::size_of_val(dest)
@@ -356,7 +482,30 @@ impl<T: ?Sized> UserRef<T> where T: UserSafe {
 impl<T> UserRef<T> where T: UserSafe {
     /// Copies the value from user memory into enclave memory.
     pub fn to_enclave(&self) -> T {
-        unsafe { ptr::read(self.0.get()) }
+        let val = unsafe { ptr::read(self.0.get()) };
+        load_fence();
+        val
+    }
+
+    /// Copies the value from user memory into enclave memory, reading every
+    /// byte of user memory exactly once.
+    ///
+    /// Unlike with [`to_enclave`], the compiler may not replace uses of the
+    /// returned value with reads from user memory, so userspace can't change
+    /// the value after it has been validated. Use this for values that are
+    /// checked before use.
+    ///
+    /// [`to_enclave`]: #method.to_enclave
+    pub fn read_once(&self) -> T {
+        unsafe {
+            let mut val = MaybeUninit::<T>::uninit();
+            copy_from_userspace(
+                self.0.get() as *const u8,
+                val.as_mut_ptr() as *mut u8,
+                mem::size_of::<T>()
+            );
+            val.assume_init()
+        }
     }
 }
 
@@ -432,6 +581,25 @@ impl<T> UserRef<[T]> where [T]: UserSafe {
         ret
     }
 
+    /// Copies the value from user memory into a vector in enclave memory,
+    /// reading every byte of user memory exactly once. See
+    /// [`UserRef::read_once`] for why this matters.
+    ///
+    /// [`UserRef::read_once`]: struct.UserRef.html#method.read_once
+    pub fn snapshot(&self) -> Vec<T> {
+        let len = self.len();
+        let mut ret = Vec::with_capacity(len);
+        unsafe {
+            copy_from_userspace(
+                self.as_ptr() as *const u8,
+                ret.as_mut_ptr() as *mut u8,
+                len * mem::size_of::<T>()
+            );
+            ret.set_len(len);
+        }
+        ret
+    }
+
     /// Returns an iterator over the slice.
     pub fn iter(&self) -> Iter<'_, T>
         where T: UserSafe // FIXME: should be implied by [T]: UserSafe?
@@ -449,6 +617,78 @@ impl<T> UserRef<[T]> where [T]: UserSafe {
             IterMut((&mut*self.as_raw_mut_ptr()).iter_mut())
         }
     }
+
+    /// Returns `true` if the slice has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the element or subslice at `index`, or `None` if it is out of
+    /// bounds.
+    pub fn get<I: SliceIndex<[T]>>(&self, index: I) -> Option<&UserRef<I::Output>>
+        where I::Output: UserSafe
+    {
+        unsafe {
+            index.get(&*self.as_raw_ptr()).map(|e| UserRef::from_ptr(e))
+        }
+    }
+
+    /// Returns the element or subslice at `index` for modification, or `None`
+    /// if it is out of bounds.
+    pub fn get_mut<I: SliceIndex<[T]>>(&mut self, index: I) -> Option<&mut UserRef<I::Output>>
+        where I::Output: UserSafe
+    {
+        unsafe {
+            index.get_mut(&mut*self.as_raw_mut_ptr()).map(|e| UserRef::from_mut_ptr(e))
+        }
+    }
+
+    /// Divides the slice into two at `mid`. The first slice contains the
+    /// elements before `mid`, the second the remaining elements.
+    ///
+    /// # Panics
+    /// This function panics if `mid > len`.
+    pub fn split_at(&self, mid: usize) -> (&UserRef<[T]>, &UserRef<[T]>) {
+        unsafe {
+            let (a, b) = (&*self.as_raw_ptr()).split_at(mid);
+            (UserRef::from_ptr(a), UserRef::from_ptr(b))
+        }
+    }
+
+    /// Divides the slice into two at `mid` for modification. The first slice
+    /// contains the elements before `mid`, the second the remaining elements.
+    ///
+    /// # Panics
+    /// This function panics if `mid > len`.
+    pub fn split_at_mut(&mut self, mid: usize) -> (&mut UserRef<[T]>, &mut UserRef<[T]>) {
+        unsafe {
+            let (a, b) = (&mut*self.as_raw_mut_ptr()).split_at_mut(mid);
+            (UserRef::from_mut_ptr(a), UserRef::from_mut_ptr(b))
+        }
+    }
+
+    /// Returns an iterator over subslices of `chunk_size` elements. The last
+    /// subslice is shorter if `chunk_size` doesn't divide the length.
+    ///
+    /// # Panics
+    /// This function panics if `chunk_size` is 0.
+    pub fn chunks(&self, chunk_size: usize) -> Chunks<'_, T> {
+        unsafe {
+            Chunks((&*self.as_raw_ptr()).chunks(chunk_size))
+        }
+    }
+
+    /// Returns an iterator over subslices of `chunk_size` elements that
+    /// allows modifying them. The last subslice is shorter if `chunk_size`
+    /// doesn't divide the length.
+    ///
+    /// # Panics
+    /// This function panics if `chunk_size` is 0.
+    pub fn chunks_mut(&mut self, chunk_size: usize) -> ChunksMut<'_, T> {
+        unsafe {
+            ChunksMut((&mut*self.as_raw_mut_ptr()).chunks_mut(chunk_size))
+        }
+    }
 }
 
 /// Immutable user slice iterator
@@ -467,8 +707,26 @@ impl<'a, T: UserSafe> Iterator for Iter<'a, T> {
             self.0.next().map(|e| UserRef::from_ptr(e))
         }
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T: UserSafe> DoubleEndedIterator for Iter<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        unsafe {
+            self.0.next_back().map(|e| UserRef::from_ptr(e))
+        }
+    }
 }
 
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T: UserSafe> ExactSizeIterator for Iter<'a, T> {}
+
 /// Mutable user slice iterator
 ///
 /// This struct is created by the `iter_mut` method on `UserRef<[T]>`.
@@ -485,8 +743,98 @@ impl<'a, T: UserSafe> Iterator for IterMut<'a, T> {
             self.0.next().map(|e| UserRef::from_mut_ptr(e))
         }
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T: UserSafe> DoubleEndedIterator for IterMut<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        unsafe {
+            self.0.next_back().map(|e| UserRef::from_mut_ptr(e))
+        }
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T: UserSafe> ExactSizeIterator for IterMut<'a, T> {}
+
+/// Immutable iterator over chunks of a user slice
+///
+/// This struct is created by the `chunks` method on `UserRef<[T]>`.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct Chunks<'a, T: 'a>(slice::Chunks<'a, T>) where [T]: UserSafe;
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T> Iterator for Chunks<'a, T> where [T]: UserSafe {
+    type Item = &'a UserRef<[T]>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            self.0.next().map(|e| UserRef::from_ptr(e))
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T> DoubleEndedIterator for Chunks<'a, T> where [T]: UserSafe {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        unsafe {
+            self.0.next_back().map(|e| UserRef::from_ptr(e))
+        }
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T> ExactSizeIterator for Chunks<'a, T> where [T]: UserSafe {}
+
+/// Mutable iterator over chunks of a user slice
+///
+/// This struct is created by the `chunks_mut` method on `UserRef<[T]>`.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct ChunksMut<'a, T: 'a>(slice::ChunksMut<'a, T>) where [T]: UserSafe;
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T> Iterator for ChunksMut<'a, T> where [T]: UserSafe {
+    type Item = &'a mut UserRef<[T]>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            self.0.next().map(|e| UserRef::from_mut_ptr(e))
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T> DoubleEndedIterator for ChunksMut<'a, T> where [T]: UserSafe {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        unsafe {
+            self.0.next_back().map(|e| UserRef::from_mut_ptr(e))
+        }
+    }
 }
 
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl<'a, T> ExactSizeIterator for ChunksMut<'a, T> where [T]: UserSafe {}
+
 #[unstable(feature = "sgx_platform", issue = "56975")]
 impl<T: ?Sized> Deref for User<T> where T: UserSafe {
     type Target = UserRef<T>;
@@ -549,14 +897,306 @@ impl UserRef<super::raw::ByteBuffer> {
     /// * The pointer in the user `ByteBuffer` is null
     /// * The pointed-to range in the user `ByteBuffer` is not in user memory
     pub fn copy_user_buffer(&self) -> Vec<u8> {
-        unsafe {
-            let buf = self.to_enclave();
-            if buf.len > 0 {
-                User::from_raw_parts(buf.data as _, buf.len).to_enclave()
-            } else {
-                // Mustn't look at `data` or call `free` if `len` is `0`.
-                Vec::with_capacity(0)
+        unsafe { OwnedByteBuffer::from_byte_buffer(self.to_enclave()).to_vec() }
+    }
+}
+
+/// A byte buffer in user memory that was returned by a usercall in a
+/// `ByteBuffer`. The buffer is freed when this value is dropped.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct OwnedByteBuffer(Option<User<[u8]>>);
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl OwnedByteBuffer {
+    /// Takes ownership of the user memory range described by `buf`.
+    ///
+    /// # Safety
+    /// Unless `buf.len` is `0`, the caller must ensure `buf.data` points to
+    /// `buf.len` bytes that are freeable with the `free` usercall and an
+    /// alignment of 1, and are uniquely owned. This is the case for buffers
+    /// returned by usercalls.
+    ///
+    /// # Panics
+    /// This function panics if `buf.len` isn't `0` and:
+    ///
+    /// * The pointer in `buf` is null
+    /// * The pointed-to range in `buf` is not in user memory
+    pub unsafe fn from_byte_buffer(buf: super::raw::ByteBuffer) -> Self {
+        if buf.len > 0 {
+            OwnedByteBuffer(Some(User::from_raw_parts(buf.data as _, buf.len)))
+        } else {
+            // Mustn't look at `data` or call `free` if `len` is `0`.
+            OwnedByteBuffer(None)
+        }
+    }
+
+    /// Returns the length of the buffer.
+    pub fn len(&self) -> usize {
+        self.0.as_ref().map_or(0, |buf| buf.len())
+    }
+
+    /// Returns `true` if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Copies the buffer to enclave memory.
+    pub fn to_vec(&self) -> Vec<u8> {
+        match self.0 {
+            Some(ref buf) => buf.to_enclave(),
+            None => Vec::with_capacity(0),
+        }
+    }
+
+    /// Converts this value into the owned buffer in user memory, or `None`
+    /// if the buffer is empty.
+    pub fn into_user(self) -> Option<User<[u8]>> {
+        self.0
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Debug for OwnedByteBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedByteBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A pool of byte buffers in user memory.
+///
+/// Enclave memory is scarce, so large transient buffers, such as those used
+/// for staging I/O, are better kept in user memory. Allocating them with the
+/// `alloc` usercall every time is slow, so a pool keeps up to `max_buffers`
+/// buffers around for reuse once they are dropped.
+///
+/// Like any user memory, the contents of the buffers are visible to and can
+/// be changed by userspace at any time.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct UserBufferPool {
+    buffers: Mutex<Vec<User<[u8]>>>,
+    max_buffers: usize,
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl UserBufferPool {
+    /// Creates an empty pool that keeps at most `max_buffers` buffers for
+    /// reuse.
+    pub fn new(max_buffers: usize) -> UserBufferPool {
+        UserBufferPool { buffers: Mutex::new(Vec::new()), max_buffers }
+    }
+
+    /// Returns a buffer of `len` bytes, reusing the smallest buffer in the
+    /// pool that is large enough, or allocating a new buffer if there is
+    /// none. The contents of the buffer are unspecified.
+    pub fn get(&self, len: usize) -> UserBuffer<'_> {
+        let buf = {
+            let mut buffers = self.buffers.lock().unwrap();
+            let smallest = buffers.iter()
+                .enumerate()
+                .filter(|&(_, buf)| buf.len() >= len)
+                .min_by_key(|&(_, buf)| buf.len())
+                .map(|(i, _)| i);
+            smallest.map(|i| buffers.swap_remove(i))
+        };
+        // Don't hold the lock during the `alloc` usercall
+        let buf = buf.unwrap_or_else(|| User::<[u8]>::uninitialized(len));
+        UserBuffer { buf: Some(buf), len, pool: self }
+    }
+
+    /// Frees all buffers kept for reuse.
+    pub fn clear(&self) {
+        let buffers = mem::replace(&mut *self.buffers.lock().unwrap(), Vec::new());
+        drop(buffers);
+    }
+
+    fn recycle(&self, buf: User<[u8]>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        } else {
+            // Don't hold the lock during the `free` usercall
+            drop(buffers);
+            drop(buf);
+        }
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Debug for UserBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserBufferPool")
+            .field("max_buffers", &self.max_buffers)
+            .finish()
+    }
+}
+
+/// A byte buffer in user memory obtained from a [`UserBufferPool`]. It is
+/// returned to the pool when dropped.
+///
+/// [`UserBufferPool`]: struct.UserBufferPool.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct UserBuffer<'a> {
+    // Always `Some` until dropped
+    buf: Option<User<[u8]>>,
+    len: usize,
+    pool: &'a UserBufferPool,
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl UserBuffer<'_> {
+    /// Returns the number of bytes the buffer can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.buf.as_ref().map_or(0, |buf| buf.len())
+    }
+
+    /// Resizes the buffer to `len` bytes. If the buffer grows beyond its
+    /// capacity, its contents are moved to a larger buffer in user memory,
+    /// and the old buffer is returned to the pool. Bytes beyond the previous
+    /// length are unspecified.
+    pub fn resize(&mut self, len: usize) {
+        if len > self.capacity() {
+            let mut new = self.pool.get(cmp::max(len, self.capacity() * 2));
+            unsafe {
+                // Both buffers are in user memory, nothing is leaked
+                ptr::copy_nonoverlapping(self.as_ptr(), new.as_mut_ptr(), self.len);
+            }
+            mem::swap(&mut self.buf, &mut new.buf);
+        }
+        self.len = len;
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl Deref for UserBuffer<'_> {
+    type Target = UserRef<[u8]>;
+
+    fn deref(&self) -> &UserRef<[u8]> {
+        let buf = self.buf.as_ref().unwrap();
+        &buf[..self.len]
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl DerefMut for UserBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut UserRef<[u8]> {
+        let len = self.len;
+        let buf = self.buf.as_mut().unwrap();
+        &mut buf[..len]
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl Drop for UserBuffer<'_> {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.recycle(buf);
+        }
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Debug for UserBuffer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserBuffer")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    struct Request {
+        id: u64,
+        len: u32,
+        flags: [u8; 4],
+    }
+
+    impl_user_safe_sized!(Request { id: u64, len: u32, flags: [u8; 4] });
+
+    #[test]
+    fn user_safe_struct() {
+        let request = Request { id: 1, len: 2, flags: [3; 4] };
+        let mut user = User::<Request>::uninitialized();
+        user.copy_from_enclave(&request);
+        assert_eq!(user.to_enclave(), request);
+    }
+
+    #[test]
+    fn copy_offsets() {
+        let src: Vec<u8> = (0..40).collect();
+        for offset in 0..8 {
+            for len in 0..32 {
+                let src = &src[offset..offset + len];
+                let user = User::<[u8]>::new_from_enclave(src);
+                let mut dest = vec![0xff; len + 1];
+                user.copy_to_enclave(&mut dest[..len]);
+                assert_eq!(&dest[..len], src);
+                assert_eq!(dest[len], 0xff);
+                assert_eq!(user.snapshot(), src);
+            }
+        }
+    }
+
+    #[test]
+    fn subslices() {
+        let user = User::<[u8]>::new_from_enclave(&[1, 2, 3, 4, 5][..]);
+        let (a, b) = user.split_at(2);
+        assert_eq!(a.to_enclave(), [1, 2]);
+        assert_eq!(b.to_enclave(), [3, 4, 5]);
+        let chunks: Vec<_> = user.chunks(2).map(|chunk| chunk.to_enclave()).collect();
+        assert_eq!(chunks, [vec![1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(user.chunks(2).len(), 3);
+        assert_eq!(user.get(1..3).map(|s| s.to_enclave()), Some(vec![2, 3]));
+        assert!(user.get(4..6).is_none());
+    }
+
+    const BENCH_LEN: usize = 64 * 1024;
+
+    #[test]
+    fn copy_head_and_tail() {
+        let data: Vec<u8> = (0..64).collect();
+        let user = User::<[u8]>::new_from_enclave(&data[..]);
+        for start in 0..16 {
+            for end in start..64 {
+                let mut dest = vec![0; end - start];
+                user[start..end].copy_to_enclave(&mut dest);
+                assert_eq!(dest, &data[start..end]);
+
+                let mut copy = User::<[u8]>::uninitialized(64);
+                copy[start..end].copy_from_enclave(&data[start..end]);
+                assert_eq!(copy[start..end].to_enclave(), &data[start..end]);
             }
         }
     }
+
+    #[bench]
+    fn bench_copy_from_enclave(b: &mut test::Bencher) {
+        let src = vec![0xa5u8; BENCH_LEN];
+        let mut dest = User::<[u8]>::uninitialized(BENCH_LEN);
+        b.bytes = BENCH_LEN as u64;
+        b.iter(|| dest.copy_from_enclave(&src));
+    }
+
+    #[bench]
+    fn bench_copy_to_enclave(b: &mut test::Bencher) {
+        let src = User::<[u8]>::new_from_enclave(&vec![0xa5u8; BENCH_LEN][..]);
+        let mut dest = vec![0u8; BENCH_LEN];
+        b.bytes = BENCH_LEN as u64;
+        b.iter(|| src.copy_to_enclave(&mut dest));
+    }
+
+    #[bench]
+    fn bench_copy_to_enclave_unaligned(b: &mut test::Bencher) {
+        let src = User::<[u8]>::new_from_enclave(&vec![0xa5u8; BENCH_LEN + 1][..]);
+        let mut dest = vec![0u8; BENCH_LEN];
+        b.bytes = BENCH_LEN as u64;
+        b.iter(|| src[1..].copy_to_enclave(&mut dest));
+    }
 }
@@ -0,0 +1,324 @@
+//! Submitting usercalls through queues in shared memory.
+//!
+//! Once set up with the `async_queues` usercall, userspace processes
+//! usercalls written to the usercall queue and writes their results to the
+//! return queue, while the enclave keeps running. An enclave can have many
+//! usercalls in flight with a single enclave exit, or none at all if
+//! userspace polls the queues.
+//!
+//! Both queues are ring buffers of `len` entries in user memory. The read
+//! offset is stored in the upper and the write offset in the lower 32 bits
+//! of the `offsets` word. Offsets count entries modulo `2 * len`, so that a
+//! full queue can be told apart from an empty one. Returns are read in the
+//! order the usercalls were submitted.
+//!
+//! Returns don't say which usercall they belong to, so that order is only
+//! as trustworthy as userspace. [`result_value`] checks a return like the
+//! synchronous usercall wrappers do, against the usercall it is assumed to
+//! belong to, which keeps lengths and pointers in bounds. But a malicious
+//! runner can still swap the returns of usercalls, so don't rely on a return
+//! belonging to a particular usercall for security.
+//!
+//! [`result_value`]: fn.result_value.html
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::fmt;
+use crate::io::{self, Error as IoError, Result as IoResult};
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use fortanix_sgx_abi::{FifoDescriptor, Return, Usercall, EV_RETURNQ_NOT_EMPTY,
+                       EV_USERCALLQ_NOT_FULL, WAIT_INDEFINITE};
+
+use super::alloc::{load_fence, User};
+use super::{check_len, check_user_range, raw, violation, wait, FromSgxResult};
+use super::raw::{Result, Usercalls};
+
+// The largest queue length for which offsets modulo `2 * len` fit in 32 bits
+const MAX_LEN: usize = 1 << 30;
+
+// One end of a queue. The enclave is either the only writer or the only
+// reader of each queue.
+struct Fifo<T> {
+    data: *mut T,
+    len: usize,
+    offsets: *const AtomicUsize,
+}
+
+fn split(offsets: usize) -> (usize, usize) {
+    (offsets >> 32, offsets & 0xffff_ffff)
+}
+
+fn join(read: usize, write: usize) -> usize {
+    (read << 32) | write
+}
+
+impl<T: Copy> Fifo<T> {
+    // Checks a queue descriptor received from userspace.
+    fn new(desc: FifoDescriptor<T>) -> Fifo<T> {
+        if desc.len == 0 || desc.len > MAX_LEN {
+            violation(format_args!("Usercall async_queues: invalid queue length {}", desc.len));
+        }
+        // Can't overflow for lengths up to `MAX_LEN` and the queue entry types
+        let size = desc.len * mem::size_of::<T>();
+        check_user_range("async_queues", desc.data as _, size, mem::align_of::<T>());
+        check_user_range("async_queues", desc.offsets as _, mem::size_of::<AtomicUsize>(),
+                         mem::align_of::<AtomicUsize>());
+        Fifo { data: desc.data, len: desc.len, offsets: desc.offsets }
+    }
+
+    fn offsets(&self) -> &AtomicUsize {
+        unsafe { &*self.offsets }
+    }
+
+    fn advance(&self, offset: usize) -> usize {
+        (offset + 1) % (2 * self.len)
+    }
+
+    fn check(&self, offset: usize) -> usize {
+        if offset >= 2 * self.len {
+            violation(format_args!("Usercall queue: invalid offset {}", offset));
+        }
+        offset
+    }
+
+    fn is_full(&self, read: usize, write: usize) -> bool {
+        (write + 2 * self.len - read) % (2 * self.len) == self.len
+    }
+
+    // Returns `Err(val)` if the queue is full.
+    fn try_push(&mut self, val: T) -> Result<(), T> {
+        let mut current = self.offsets().load(Ordering::Acquire);
+        let (read, write) = split(current);
+        let (read, write) = (self.check(read), self.check(write));
+        if self.is_full(read, write) {
+            return Err(val);
+        }
+        unsafe { ptr::write_volatile(self.data.add(write % self.len), val) };
+        // Userspace may concurrently advance the read offset
+        loop {
+            let new = join(split(current).0, self.advance(write));
+            match self.offsets().compare_exchange(current, new,
+                                                  Ordering::Release, Ordering::Acquire) {
+                Ok(_) => return Ok(()),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    // Returns `None` if the queue is empty.
+    fn try_pop(&mut self) -> Option<T> {
+        let mut current = self.offsets().load(Ordering::Acquire);
+        let (read, write) = split(current);
+        let (read, write) = (self.check(read), self.check(write));
+        if read == write {
+            return None;
+        }
+        let val = unsafe { ptr::read_volatile(self.data.add(read % self.len)) };
+        load_fence();
+        // Userspace may concurrently advance the write offset
+        loop {
+            let new = join(self.advance(read), split(current).1);
+            match self.offsets().compare_exchange(current, new,
+                                                  Ordering::Release, Ordering::Acquire) {
+                Ok(_) => return Some(val),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+static CREATED: AtomicBool = AtomicBool::new(false);
+
+/// The usercall and return queues of the enclave.
+///
+/// Only one `AsyncQueues` can be created per enclave.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct AsyncQueues {
+    usercalls: Fifo<Usercall>,
+    returns: Fifo<Return>,
+}
+
+// The queue pointers are in user memory and are only accessed atomically or
+// through `&mut self`.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+unsafe impl Send for AsyncQueues {}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl AsyncQueues {
+    /// Asks userspace to set up the queues with the `async_queues` usercall.
+    ///
+    /// Returns an error if the queues were already set up. The enclave is
+    /// aborted if userspace returns invalid queues.
+    pub fn new() -> IoResult<AsyncQueues> {
+        if CREATED.swap(true, Ordering::SeqCst) {
+            return Err(IoError::new(io::ErrorKind::AlreadyExists,
+                                    "usercall queues were already set up"));
+        }
+        unsafe {
+            let mut usercalls = User::<FifoDescriptor<Usercall>>::uninitialized();
+            let mut returns = User::<FifoDescriptor<Return>>::uninitialized();
+            raw::async_queues(usercalls.as_raw_mut_ptr(), returns.as_raw_mut_ptr())
+                .from_sgx_result()?;
+            Ok(AsyncQueues {
+                usercalls: Fifo::new(usercalls.read_once()),
+                returns: Fifo::new(returns.read_once()),
+            })
+        }
+    }
+
+    /// Submits `usercall`, or returns it if the usercall queue is full.
+    pub fn try_submit(&mut self, usercall: Usercall) -> Result<(), Usercall> {
+        self.usercalls.try_push(usercall)
+    }
+
+    /// Submits `usercall`, waiting for space in the usercall queue if it is
+    /// full.
+    pub fn submit(&mut self, mut usercall: Usercall) {
+        while let Err(u) = self.usercalls.try_push(usercall) {
+            usercall = u;
+            rtunwrap!(Ok, wait(EV_USERCALLQ_NOT_FULL, WAIT_INDEFINITE));
+        }
+    }
+
+    /// Returns the result of the oldest completed usercall, or `None` if the
+    /// return queue is empty. The return isn't checked, decode it with
+    /// [`result_value`](fn.result_value.html) or [`result`](fn.result.html).
+    pub fn try_complete(&mut self) -> Option<Return> {
+        self.returns.try_pop()
+    }
+
+    /// Returns the result of the oldest completed usercall, waiting for one
+    /// if the return queue is empty. The return isn't checked, like with
+    /// [`try_complete`](#method.try_complete).
+    pub fn complete(&mut self) -> Return {
+        loop {
+            if let Some(ret) = self.returns.try_pop() {
+                return ret;
+            }
+            rtunwrap!(Ok, wait(EV_RETURNQ_NOT_EMPTY, WAIT_INDEFINITE));
+        }
+    }
+
+    /// Submits all of `usercalls` and waits for their results, which are
+    /// returned in the same order.
+    ///
+    /// As long as the queues have room for the whole batch, this exits the
+    /// enclave at most once, to wait for the results. Results of usercalls
+    /// submitted earlier must have been completed before calling this
+    /// function, otherwise they are mistaken for results of the batch.
+    ///
+    /// Every return is checked with [`result_value`](fn.result_value.html)
+    /// against the usercall at the same position, aborting the enclave if it
+    /// violates the usercall ABI. See the module documentation for why the
+    /// order of the returns can't be trusted.
+    pub fn run_batch(&mut self, usercalls: &[Usercall]) -> Vec<Return> {
+        let mut returns = Vec::with_capacity(usercalls.len());
+        for &usercall in usercalls {
+            // Make room by collecting results if the usercall queue is full
+            let mut usercall = usercall;
+            while let Err(u) = self.usercalls.try_push(usercall) {
+                usercall = u;
+                match self.returns.try_pop() {
+                    Some(ret) => returns.push(ret),
+                    None => {
+                        rtunwrap!(Ok, wait(EV_USERCALLQ_NOT_FULL | EV_RETURNQ_NOT_EMPTY,
+                                           WAIT_INDEFINITE));
+                    }
+                }
+            }
+        }
+        while returns.len() < usercalls.len() {
+            returns.push(self.complete());
+        }
+        for (usercall, &ret) in usercalls.iter().zip(&returns) {
+            let _ = result_value(usercall, ret);
+        }
+        returns
+    }
+}
+
+/// Returns the usercall queue entry for usercall `nr` with the arguments
+/// `args`, see the raw function definitions for the arguments of each
+/// usercall.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn usercall(nr: Usercalls, args: [u64; 4]) -> Usercall {
+    Usercall(nr as u64, args[0], args[1], args[2], args[3])
+}
+
+/// Decodes the return `ret` of `usercall`, a usercall that returns a result
+/// and a value, such as `read` or `write`.
+///
+/// The value is checked like the synchronous usercall wrappers do: lengths
+/// returned by `read` and `write` must be at most the requested length,
+/// memory returned by `alloc` must be in user memory, and events returned by
+/// `wait` must be in the event mask. The enclave is aborted otherwise.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn result_value(usercall: &Usercall, ret: Return) -> IoResult<u64> {
+    let value = (ret.0 as Result, ret.1).from_sgx_result()?;
+    let Usercall(nr, p1, p2, p3, _) = *usercall;
+    if nr == Usercalls::read as u64 {
+        check_len("read", value as usize, p3 as usize);
+    } else if nr == Usercalls::write as u64 {
+        check_len("write", value as usize, p3 as usize);
+    } else if nr == Usercalls::alloc as u64 {
+        check_user_range("alloc", value as *const u8, p1 as usize, p2 as usize);
+    } else if nr == Usercalls::wait as u64 && value & !p1 != 0 {
+        violation(format_args!("Usercall wait: returned events {:#x} not in the mask {:#x}",
+                               value, p1));
+    }
+    Ok(value)
+}
+
+/// Decodes the return of a usercall that only returns a result, such as
+/// `flush` or `send`.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn result(ret: Return) -> IoResult<()> {
+    if ret.1 != 0 {
+        violation(format_args!("Usercall queue: unexpected second return value {}", ret.1));
+    }
+    (ret.0 as Result).from_sgx_result()
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Debug for AsyncQueues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncQueues")
+            .field("usercall_queue_len", &self.usercalls.len)
+            .field("return_queue_len", &self.returns.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_and_empty() {
+        let mut data = [0u64; 2];
+        let offsets = AtomicUsize::new(0);
+        let mut fifo = Fifo { data: data.as_mut_ptr(), len: 2, offsets: &offsets };
+        assert_eq!(fifo.try_pop(), None);
+        assert_eq!(fifo.try_push(1), Ok(()));
+        assert_eq!(fifo.try_push(2), Ok(()));
+        assert_eq!(fifo.try_push(3), Err(3));
+        assert_eq!(fifo.try_pop(), Some(1));
+        assert_eq!(fifo.try_push(3), Ok(()));
+        assert_eq!(offsets.load(Ordering::SeqCst), join(1, 3));
+        assert_eq!(fifo.try_pop(), Some(2));
+        assert_eq!(fifo.try_pop(), Some(3));
+        assert_eq!(fifo.try_pop(), None);
+        assert_eq!(offsets.load(Ordering::SeqCst), join(3, 3));
+    }
+
+    #[test]
+    fn decode_returns() {
+        let read = usercall(Usercalls::read, [0, 0, 5, 0]);
+        assert_eq!(result_value(&read, Return(0, 5)).unwrap(), 5);
+        let err = result_value(&read, Return(raw::Error::NotFound as u64, 0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(result(Return(0, 0)).is_ok());
+    }
+}
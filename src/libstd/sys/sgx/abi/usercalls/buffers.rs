@@ -0,0 +1,105 @@
+//! Free lists of byte buffers in user memory.
+//!
+//! `read` and `write` need a buffer in user memory for every call. Buffers of
+//! common sizes are kept in per-size-class free lists when they are dropped,
+//! so that steady-state I/O doesn't need the `alloc` and `free` usercalls.
+
+use crate::mem;
+use crate::ops::{Deref, DerefMut};
+use crate::sys::waitqueue::SpinMutex;
+
+use super::alloc::{User, UserRef};
+
+// Size classes are powers of two from 4 KiB to 64 KiB
+const MIN_CLASS_SHIFT: usize = 12;
+const CLASSES: usize = 5;
+const BUFFERS_PER_CLASS: usize = 4;
+
+// The addresses of the free buffers of each class, or 0
+static FREE: SpinMutex<[[usize; BUFFERS_PER_CLASS]; CLASSES]> =
+    SpinMutex::new([[0; BUFFERS_PER_CLASS]; CLASSES]);
+
+fn class_size(class: usize) -> usize {
+    1 << (MIN_CLASS_SHIFT + class)
+}
+
+// Returns the smallest class that can hold `len` bytes, if there is one.
+fn class(len: usize) -> Option<usize> {
+    (0..CLASSES).find(|&class| class_size(class) >= len)
+}
+
+fn take(class: usize) -> Option<User<[u8]>> {
+    let ptr = {
+        let mut free = FREE.lock();
+        let slot = free[class].iter_mut().find(|ptr| **ptr != 0)?;
+        mem::replace(slot, 0)
+    };
+    Some(unsafe { User::from_raw_parts(ptr as *mut u8, class_size(class)) })
+}
+
+/// A buffer of user memory that is returned to the free lists when dropped.
+pub(super) struct Buffer {
+    // Always `Some` until dropped
+    buf: Option<User<[u8]>>,
+    len: usize,
+    class: Option<usize>,
+}
+
+/// Returns a buffer of `len` bytes, reusing a free one if possible. The
+/// contents of the buffer are unspecified.
+pub(super) fn get(len: usize) -> Buffer {
+    let class = class(len);
+    let buf = match class.and_then(take) {
+        Some(buf) => buf,
+        None => User::<[u8]>::uninitialized(class.map_or(len, class_size)),
+    };
+    Buffer { buf: Some(buf), len, class }
+}
+
+impl Deref for Buffer {
+    type Target = UserRef<[u8]>;
+
+    fn deref(&self) -> &UserRef<[u8]> {
+        &self.buf.as_ref().unwrap()[..self.len]
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut UserRef<[u8]> {
+        let len = self.len;
+        &mut self.buf.as_mut().unwrap()[..len]
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let (buf, class) = match (self.buf.take(), self.class) {
+            (Some(buf), Some(class)) => (buf, class),
+            _ => return, // freed with the `free` usercall
+        };
+        let ptr = buf.into_raw() as *mut u8 as usize;
+        {
+            let mut free = FREE.lock();
+            if let Some(slot) = free[class].iter_mut().find(|ptr| **ptr == 0) {
+                *slot = ptr;
+                return;
+            }
+        }
+        // The free list is full, don't hold the lock during the usercall
+        drop(unsafe { User::from_raw_parts(ptr as *mut u8, class_size(class)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes() {
+        assert_eq!(class(0), Some(0));
+        assert_eq!(class(4096), Some(0));
+        assert_eq!(class(4097), Some(1));
+        assert_eq!(class(64 * 1024), Some(CLASSES - 1));
+        assert_eq!(class(64 * 1024 + 1), None);
+    }
+}
@@ -0,0 +1,64 @@
+//! Injecting faults into usercalls.
+//!
+//! When the standard library is built with the `sgx-usercall-faults` feature,
+//! tests can install an interposer that sees every usercall before it is
+//! made, and can answer it in place of userspace. This makes it possible to
+//! test how code handles errors, short reads and writes, or delays, which a
+//! well-behaved runner never produces.
+//!
+//! Values returned by the interposer are checked like values returned by
+//! userspace, so returning a value that violates the usercall ABI, such as a
+//! length larger than the buffer, aborts the enclave.
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::cell::Cell;
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicPtr, Ordering};
+
+use super::super::tls::Tls;
+
+/// A function that is called before the usercall `nr` is made with arguments
+/// `args`. If it returns `Some`, the usercall isn't made, and the returned
+/// registers are used as its return value instead.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub type UsercallInterposer = fn(nr: u64, args: [u64; 4]) -> Option<(u64, u64)>;
+
+static INTERPOSER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+thread_local! {
+    static IN_INTERPOSER: Cell<bool> = Cell::new(false);
+}
+
+/// Installs `interposer` to be called for every usercall, replacing the
+/// previously installed interposer. If `interposer` is `None`, usercalls are
+/// made normally.
+///
+/// Usercalls made by the interposer, for example to forward a shortened
+/// `read`, are made normally. So are usercalls that don't return, usercalls
+/// made by a thread before or after it can use thread-local storage, and
+/// usercalls submitted through the asynchronous usercall queue. Usercalls
+/// answered by the interposer don't invoke the usercall trace hooks.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn set_usercall_interposer(interposer: Option<UsercallInterposer>) {
+    let interposer = interposer.map_or(ptr::null_mut(), |interposer| interposer as *mut ());
+    INTERPOSER.store(interposer, Ordering::SeqCst);
+}
+
+/// Calls the interposer if one is installed, returning its answer.
+#[inline]
+pub(super) fn interpose(nr: u64, args: [u64; 4]) -> Option<(u64, u64)> {
+    let interposer = INTERPOSER.load(Ordering::SeqCst);
+    if interposer.is_null() || !Tls::is_active() {
+        return None;
+    }
+    let interposer: UsercallInterposer = unsafe { mem::transmute(interposer) };
+    match IN_INTERPOSER.try_with(|in_interposer| in_interposer.replace(true)) {
+        Ok(false) => {
+            let ret = interposer(nr, args);
+            IN_INTERPOSER.with(|in_interposer| in_interposer.set(false));
+            ret
+        }
+        _ => None,
+    }
+}
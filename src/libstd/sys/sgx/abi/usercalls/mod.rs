@@ -1,23 +1,65 @@
-use crate::io::{Error as IoError, Result as IoResult};
-use crate::time::Duration;
+use crate::cmp;
+use crate::fmt;
+use crate::io::{self, Error as IoError, IoVec, IoVecMut, Result as IoResult};
+use crate::time::{Duration, Instant};
 
 pub(crate) mod alloc;
+pub(crate) mod async_queues;
+#[cfg(feature = "sgx-usercall-faults")]
+pub(crate) mod fault;
+#[cfg(feature = "sgx-usercall-trace")]
+pub(crate) mod trace;
+mod buffers;
 #[macro_use]
 pub(crate) mod raw;
 
 use self::raw::*;
+use super::mem::validate_user_range;
+use crate::sys::abort::{abort_with_code, print_err_fmt, AbortReason};
+
+// How many bytes `write_vectored` gathers into one buffer, the size of the
+// largest buffers that are reused
+const MAX_GATHER_LEN: usize = 64 * 1024;
 
 /// Usercall `read`. See the ABI documentation for more information.
+// FIXME: Blocking usercalls such as `read` and `accept_stream` can't be
+// interrupted. Sending an event to the blocked thread doesn't help, since
+// only `wait` returns on events. Cancellation needs a usercall or event bit
+// that makes userspace return `Interrupted` from the blocked call, which the
+// ABI doesn't have yet.
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn read(fd: Fd, buf: &mut [u8]) -> IoResult<usize> {
     unsafe {
-        let mut userbuf = alloc::User::<[u8]>::uninitialized(buf.len());
+        let mut userbuf = buffers::get(buf.len());
         let len = raw::read(fd, userbuf.as_mut_ptr(), userbuf.len()).from_sgx_result()?;
+        let len = check_len("read", len, buf.len());
         userbuf[..len].copy_to_enclave(&mut buf[..len]);
         Ok(len)
     }
 }
 
+/// Usercall `read` into several buffers. Like [`read`], this makes a single
+/// usercall, and fills the buffers in order.
+///
+/// [`read`]: fn.read.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn read_vectored(fd: Fd, bufs: &mut [IoVecMut<'_>]) -> IoResult<usize> {
+    unsafe {
+        // Can't overflow, the buffers don't overlap
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        let mut userbuf = buffers::get(total);
+        let len = raw::read(fd, userbuf.as_mut_ptr(), userbuf.len()).from_sgx_result()?;
+        let len = check_len("read", len, total);
+        let mut copied = 0;
+        for buf in bufs {
+            let n = cmp::min(buf.len(), len - copied);
+            userbuf[copied..copied + n].copy_to_enclave(&mut buf[..n]);
+            copied += n;
+        }
+        Ok(len)
+    }
+}
+
 /// Usercall `read_alloc`. See the ABI documentation for more information.
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn read_alloc(fd: Fd) -> IoResult<Vec<u8>> {
@@ -25,7 +67,7 @@ pub fn read_alloc(fd: Fd) -> IoResult<Vec<u8>> {
         let userbuf = ByteBuffer { data: crate::ptr::null_mut(), len: 0 };
         let mut userbuf = alloc::User::new_from_enclave(&userbuf);
         raw::read_alloc(fd, userbuf.as_raw_mut_ptr()).from_sgx_result()?;
-        Ok(userbuf.copy_user_buffer())
+        Ok(copy_returned_buffer("read_alloc", &userbuf))
     }
 }
 
@@ -33,8 +75,90 @@ pub fn read_alloc(fd: Fd) -> IoResult<Vec<u8>> {
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn write(fd: Fd, buf: &[u8]) -> IoResult<usize> {
     unsafe {
-        let userbuf = alloc::User::new_from_enclave(buf);
-        raw::write(fd, userbuf.as_ptr(), userbuf.len()).from_sgx_result()
+        let mut userbuf = buffers::get(buf.len());
+        userbuf.copy_from_enclave(buf);
+        let len = raw::write(fd, userbuf.as_ptr(), userbuf.len()).from_sgx_result()?;
+        Ok(check_len("write", len, buf.len()))
+    }
+}
+
+/// Usercall `write` from several buffers. Like [`write`], this makes a single
+/// usercall, writing the buffers in order. At most 64 KiB are written.
+///
+/// [`write`]: fn.write.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn write_vectored(fd: Fd, bufs: &[IoVec<'_>]) -> IoResult<usize> {
+    unsafe {
+        // The buffers may overlap, so their lengths can add up to more than
+        // the address space. Only gather part of them if there is a lot of
+        // data, the write is allowed to be partial.
+        let total = bufs.iter().fold(0, |total: usize, buf| total.saturating_add(buf.len()));
+        let total = cmp::min(total, MAX_GATHER_LEN);
+        let mut userbuf = buffers::get(total);
+        let mut copied = 0;
+        for buf in bufs {
+            let n = cmp::min(buf.len(), total - copied);
+            userbuf[copied..copied + n].copy_from_enclave(&buf[..n]);
+            copied += n;
+        }
+        let len = raw::write(fd, userbuf.as_ptr(), userbuf.len()).from_sgx_result()?;
+        Ok(check_len("write", len, total))
+    }
+}
+
+/// Usercall `read` into a buffer in user memory. Unlike [`read`], the data
+/// isn't copied into the enclave.
+///
+/// [`read`]: fn.read.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn read_user(fd: Fd, buf: &mut alloc::UserRef<[u8]>) -> IoResult<usize> {
+    unsafe {
+        let len = raw::read(fd, buf.as_mut_ptr(), buf.len()).from_sgx_result()?;
+        Ok(check_len("read", len, buf.len()))
+    }
+}
+
+/// Usercall `write` from a buffer in user memory. Unlike [`write`], the data
+/// isn't copied out of the enclave first.
+///
+/// [`write`]: fn.write.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn write_user(fd: Fd, buf: &alloc::UserRef<[u8]>) -> IoResult<usize> {
+    unsafe {
+        let len = raw::write(fd, buf.as_ptr(), buf.len()).from_sgx_result()?;
+        Ok(check_len("write", len, buf.len()))
+    }
+}
+
+/// Copies all data from `reader` to `writer` through a buffer in user
+/// memory, returning the number of bytes copied. The data never enters the
+/// enclave.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+// FIXME: The staging buffer could match the buffer sizes of the streams if
+// userspace reported them, see `ext::net::TcpStreamExt`.
+pub fn copy(reader: Fd, writer: Fd) -> IoResult<u64> {
+    let mut buf = buffers::get(64 * 1024);
+    let mut copied = 0;
+    loop {
+        let len = match read_user(reader, &mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut written = 0;
+        while written < len {
+            match write_user(writer, &buf[written..len]) {
+                Ok(0) => {
+                    return Err(IoError::new(io::ErrorKind::WriteZero,
+                                            "failed to write whole buffer"));
+                }
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        copied += len as u64;
     }
 }
 
@@ -51,8 +175,9 @@ pub fn close(fd: Fd) {
 }
 
 fn string_from_bytebuffer(buf: &alloc::UserRef<ByteBuffer>, usercall: &str, arg: &str) -> String {
-    String::from_utf8(buf.copy_user_buffer())
-        .unwrap_or_else(|_| rtabort!("Usercall {}: expected {} to be valid UTF-8", usercall, arg))
+    String::from_utf8(copy_returned_buffer(usercall, buf)).unwrap_or_else(|_| {
+        violation(format_args!("Usercall {}: expected {} to be valid UTF-8", usercall, arg))
+    })
 }
 
 /// Usercall `bind_stream`. See the ABI documentation for more information.
@@ -118,6 +243,12 @@ pub unsafe fn launch_thread() -> IoResult<()> {
 }
 
 /// Usercall `exit`. See the ABI documentation for more information.
+// FIXME: The runner only learns whether the enclave panicked, not why. Debug
+// enclaves get the panic message in the debug panic buffer (see
+// `abi::panic::SgxPanicOutput`), and the crash report printed to standard
+// error when the enclave aborts includes it (see `sys::abort`), but there is
+// no way to pass a message or location along with `exit` until the usercall
+// ABI gains a variant that takes a user buffer.
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn exit(panic: bool) -> ! {
     unsafe { raw::exit(panic) }
@@ -126,10 +257,69 @@ pub fn exit(panic: bool) -> ! {
 /// Usercall `wait`. See the ABI documentation for more information.
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn wait(event_mask: u64, timeout: u64) -> IoResult<u64> {
-    unsafe { raw::wait(event_mask, timeout).from_sgx_result() }
+    let eventset = unsafe { raw::wait(event_mask, timeout).from_sgx_result()? };
+    if eventset & !event_mask != 0 {
+        violation(format_args!("Usercall wait: returned events {:#x} not in the mask {:#x}",
+                               eventset, event_mask));
+    }
+    Ok(eventset)
+}
+
+/// Usercall `wait` with a `Duration` as the timeout, or no timeout if
+/// `timeout` is `None`. See the ABI documentation for more information.
+///
+/// Timeouts that don't fit the usercall argument are shortened to the
+/// longest timeout that does.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn wait_for(event_mask: u64, timeout: Option<Duration>) -> IoResult<u64> {
+    let timeout = match timeout {
+        None => WAIT_INDEFINITE,
+        // `WAIT_INDEFINITE` is all ones, so the largest finite timeout is one less.
+        Some(timeout) => cmp::min((WAIT_INDEFINITE - 1) as u128, timeout.as_nanos()) as u64,
+    };
+    wait(event_mask, timeout)
+}
+
+/// Usercall `wait` with a timeout.
+///
+/// Waits for any of the events in `event_mask` for at most `duration`. Since
+/// userspace may send spurious events, this keeps waiting until either
+/// `should_wake_up` returns `true` after an event was received, or the
+/// timeout has elapsed.
+pub(crate) fn wait_timeout<F>(event_mask: u64, duration: Duration, should_wake_up: F)
+    where F: Fn() -> bool
+{
+    // Calls the wait usercall and checks the result. Returns `true` if an
+    // event was returned, and `false` if `WouldBlock`/`TimedOut` was returned.
+    fn wait_checked(event_mask: u64, timeout: Duration) -> bool {
+        match wait_for(event_mask, Some(timeout)) {
+            Ok(_) => true,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut ||
+                          e.kind() == io::ErrorKind::WouldBlock => false,
+            Err(e) => violation(format_args!("Usercall wait: returned unexpected error {}", e)),
+        }
+    }
+
+    let start = Instant::now();
+    loop {
+        let remaining = match duration.checked_sub(start.elapsed()) {
+            Some(remaining) => remaining,
+            None => return, // timed out
+        };
+        match wait_checked(event_mask, remaining) {
+            false => return, // timed out
+            true if should_wake_up() => return, // woken up
+            true => {} // spurious event
+        }
+    }
 }
 
 /// Usercall `send`. See the ABI documentation for more information.
+// FIXME: Events carry no data, so the runner can't push control messages
+// (configuration updates, shutdown requests) into the enclave other than
+// over a stream it pretends is a network connection. A message channel needs
+// `send_msg`/`recv_msg` usercalls taking a channel ID and a user buffer,
+// plus an event for pending messages, none of which the usercall ABI has.
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn send(event_set: u64, tcs: Option<Tcs>) -> IoResult<()> {
     unsafe { raw::send(event_set, tcs).from_sgx_result() }
@@ -145,7 +335,9 @@ pub fn insecure_time() -> Duration {
 /// Usercall `alloc`. See the ABI documentation for more information.
 #[unstable(feature = "sgx_platform", issue = "56975")]
 pub fn alloc(size: usize, alignment: usize) -> IoResult<*mut u8> {
-    unsafe { raw::alloc(size, alignment).from_sgx_result() }
+    let ptr = unsafe { raw::alloc(size, alignment).from_sgx_result()? };
+    check_user_range("alloc", ptr, size, alignment);
+    Ok(ptr)
 }
 
 #[unstable(feature = "sgx_platform", issue = "56975")]
@@ -176,8 +368,47 @@ fn check_os_error(err: Result) -> i32 {
     {
         err
     } else {
-        rtabort!("Usercall: returned invalid error value {}", err)
+        violation(format_args!("Usercall: returned invalid error value {}", err))
+    }
+}
+
+// Values returned by userspace can't be trusted, so everything a usercall
+// returns goes through the checks below before it is used. Userspace that
+// violates the ABI is either broken or malicious, and continuing could leak
+// or corrupt enclave memory, so the enclave is aborted.
+
+#[cold]
+#[inline(never)]
+fn violation(msg: fmt::Arguments<'_>) -> ! {
+    print_err_fmt(format_args!("{}\n", msg));
+    abort_with_code(AbortReason::UsercallViolation)
+}
+
+// Checks a length returned by `usercall`, which must be at most `max`.
+fn check_len(usercall: &str, len: usize, max: usize) -> usize {
+    if len > max {
+        violation(format_args!("Usercall {}: returned length {} larger than {}",
+                               usercall, len, max));
+    }
+    len
+}
+
+// Checks a pointer to `len` bytes of user memory returned by `usercall`.
+fn check_user_range(usercall: &str, p: *const u8, len: usize, align: usize) {
+    if let Err(e) = validate_user_range(p, len, align) {
+        violation(format_args!("Usercall {}: returned invalid pointer {:p}: {}",
+                               usercall, p, e));
+    }
+}
+
+// Copies a buffer returned by `usercall` into the enclave and frees it.
+fn copy_returned_buffer(usercall: &str, buf: &alloc::UserRef<ByteBuffer>) -> Vec<u8> {
+    // Read the descriptor once, userspace may change it after it is checked
+    let buf = buf.to_enclave();
+    if buf.len > 0 {
+        check_user_range(usercall, buf.data, buf.len, 1);
     }
+    unsafe { alloc::OwnedByteBuffer::from_byte_buffer(buf).to_vec() }
 }
 
 trait FromSgxResult {
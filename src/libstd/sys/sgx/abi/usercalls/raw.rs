@@ -28,7 +28,23 @@ extern "C" {
 pub unsafe fn do_usercall(nr: NonZeroU64, p1: u64, p2: u64, p3: u64, p4: u64, abort: bool)
     -> (u64, u64)
 {
+    crate::sys::ext::perf::sample();
+    #[cfg(feature = "sgx-usercall-faults")]
+    {
+        if !abort {
+            if let Some(ret) = super::fault::interpose(nr.get(), [p1, p2, p3, p4]) {
+                return ret;
+            }
+        }
+    }
+    #[cfg(feature = "sgx-usercall-trace")]
+    let trace = super::trace::before(nr.get(), [p1, p2, p3, p4]);
+    let start = crate::sys::ext::perf::usercall_start();
     let UsercallReturn(a, b) = usercall(nr, p1, p2, abort as _, p3, p4);
+    crate::sys::ext::perf::usercall_done(nr.get(), start);
+    super::alloc::load_fence();
+    #[cfg(feature = "sgx-usercall-trace")]
+    super::trace::after(trace, (a, b));
     (a, b)
 }
 
@@ -246,4 +262,9 @@ macro_rules! enclave_usercalls_internal_define_usercalls {
     );
 }
 
+// FIXME: The enclave can't tell which usercalls the runner implements. A
+// runner that doesn't know a usercall either aborts or returns an error that
+// can't be told apart from a real failure. Degrading gracefully requires a
+// handshake at startup, in which the runner reports the ABI version or a
+// capability bitmask, and that needs a usercall reserved for it in the ABI.
 invoke_with_usercalls!(define_usercalls);
@@ -0,0 +1,92 @@
+//! Tracing usercalls.
+//!
+//! When the standard library is built with the `sgx-usercall-trace` feature,
+//! hooks can be installed that are called before and after every usercall,
+//! for example to keep an audit log of everything crossing the enclave
+//! boundary, or to count usercalls by type.
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::cell::Cell;
+use crate::ptr;
+use crate::sync::atomic::{AtomicPtr, Ordering};
+use crate::sys::time::rdtsc;
+
+use super::super::tls::Tls;
+
+/// Functions that are called before and after every usercall.
+///
+/// Usercalls made by a hook, and usercalls made by a thread before or after
+/// it can use thread-local storage, don't invoke the hooks. Neither do
+/// usercalls submitted through the asynchronous usercall queue.
+#[derive(Clone, Copy, Debug)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct UsercallHooks {
+    /// Called before the usercall `nr` is made with arguments `args`.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub before: fn(nr: u64, args: [u64; 4]),
+    /// Called after the usercall `nr` returned `ret`, which took `ticks`
+    /// time-stamp counter ticks. `ticks` is `0` unless the standard library
+    /// is built with the `sgx-rdtsc` feature, which requires SGX2. Not called
+    /// for usercalls that don't return.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub after: fn(nr: u64, ret: (u64, u64), ticks: u64),
+}
+
+static HOOKS: AtomicPtr<UsercallHooks> = AtomicPtr::new(ptr::null_mut());
+
+thread_local! {
+    static IN_HOOK: Cell<bool> = Cell::new(false);
+}
+
+/// Installs `hooks` to be called for every usercall, replacing the previously
+/// installed hooks. If `hooks` is `None`, no hooks are called.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn set_usercall_hooks(hooks: Option<&'static UsercallHooks>) {
+    let hooks = hooks.map_or(ptr::null_mut(), |hooks| hooks as *const _ as *mut _);
+    HOOKS.store(hooks, Ordering::SeqCst);
+}
+
+// Runs `f` unless the current thread is already running a hook.
+fn in_hook<F: FnOnce()>(f: F) -> bool {
+    if !Tls::is_active() {
+        return false;
+    }
+    match IN_HOOK.try_with(|in_hook| in_hook.replace(true)) {
+        Ok(false) => {
+            f();
+            IN_HOOK.with(|in_hook| in_hook.set(false));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// What is needed to call the `after` hook.
+pub(super) struct Trace {
+    hooks: &'static UsercallHooks,
+    nr: u64,
+    start: u64,
+}
+
+/// Calls the `before` hook if hooks are installed.
+#[inline]
+pub(super) fn before(nr: u64, args: [u64; 4]) -> Option<Trace> {
+    let hooks = HOOKS.load(Ordering::SeqCst);
+    if hooks.is_null() {
+        return None;
+    }
+    let hooks = unsafe { &*hooks };
+    if !in_hook(|| (hooks.before)(nr, args)) {
+        return None;
+    }
+    Some(Trace { hooks, nr, start: rdtsc().unwrap_or(0) })
+}
+
+/// Calls the `after` hook of a usercall that `before` returned `trace` for.
+#[inline]
+pub(super) fn after(trace: Option<Trace>, ret: (u64, u64)) {
+    if let Some(trace) = trace {
+        let ticks = rdtsc().map_or(0, |now| now.wrapping_sub(trace.start));
+        in_hook(|| (trace.hooks.after)(trace.nr, ret, ticks));
+    }
+}
@@ -0,0 +1,292 @@
+//! Aborting the enclave with a reason.
+//!
+//! Every way libstd can abort the enclave is assigned a reason with a stable
+//! numeric code, which is printed before exiting so failures can be told
+//! apart.
+//!
+//! Panic messages are normally only printed in debug enclaves. To still give
+//! some clue when a thread panics while panicking, the message of the first
+//! panic is remembered, and both messages are printed to standard error (or
+//! the installed error output sink) before aborting.
+
+use core::panic::BoxMeUp;
+
+use crate::cell::{Cell, RefCell};
+use crate::cmp;
+use crate::fmt::{self, Write};
+use crate::io;
+use crate::sync::atomic::{AtomicBool, Ordering};
+
+use super::abi::usercalls;
+use super::ext::{self, error_output};
+use super::stdio::Stderr;
+use super::waitqueue::SpinMutex;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AbortReason {
+    /// `rtabort!`, `process::abort` and other fatal runtime errors.
+    Abort = 1,
+    /// The allocation error handler returned.
+    OutOfMemory = 2,
+    /// A thread panicked while it was already panicking.
+    DoublePanic = 3,
+    /// `__rust_abort` was called, by the unwinder or the `panic=abort`
+    /// runtime.
+    PanicRuntime = 4,
+    /// A lock was used in a way that violates its contract.
+    RwLockMisuse = 5,
+    /// The guard word after a heap allocation was overwritten. Only detected
+    /// with debug assertions.
+    HeapOverflow = 6,
+    /// A heap allocation was freed twice. Only detected with debug
+    /// assertions.
+    DoubleFree = 7,
+    /// Userspace returned a value from a usercall that violates the usercall
+    /// ABI.
+    UsercallViolation = 8,
+    /// A thread was about to block on a lock in a way that can never be woken.
+    /// Only detected with debug assertions.
+    Deadlock = 9,
+    /// A hardware exception that can't be turned into a panic.
+    HardwareException = 10,
+    /// A thread ran out of stack space.
+    StackOverflow = 11,
+}
+
+impl AbortReason {
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            AbortReason::Abort => "fatal runtime error",
+            AbortReason::OutOfMemory => "out of memory",
+            AbortReason::DoublePanic => "panic while panicking",
+            AbortReason::PanicRuntime => "abort requested by the panic runtime or unwinder",
+            AbortReason::RwLockMisuse => "reader-writer lock misuse",
+            AbortReason::HeapOverflow => "heap buffer overflow",
+            AbortReason::DoubleFree => "double free",
+            AbortReason::UsercallViolation => "invalid value returned by a usercall",
+            AbortReason::Deadlock => "deadlock",
+            AbortReason::HardwareException => "hardware exception",
+            AbortReason::StackOverflow => "thread stack overflow",
+        }
+    }
+}
+
+pub fn abort_with_code(reason: AbortReason) -> ! {
+    print_err_fmt(format_args!("enclave aborted with code {}: {}\n",
+                               reason.code(), reason.description()));
+    // Collecting the report may itself abort, don't try again.
+    if !REPORTING.swap(true, Ordering::SeqCst) {
+        crash_report();
+    }
+    ext::abort::run_hooks();
+    // FIXME: Pass the code to the runner once the `exit` usercall can carry
+    // more than a panic flag.
+    usercalls::exit(true)
+}
+
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+// Prints what is known about the state of the enclave when it aborts.
+//
+// FIXME: The runner should receive this as a structured record, including the
+// register state if the abort was caused by an exception, but there is no
+// usercall for that.
+fn crash_report() {
+    if crate::thread::panicking() {
+        let _ = FIRST_PANIC.try_with(|first| {
+            if let Ok(first) = first.try_borrow() {
+                print_err_parts(&[b"  while panicking at ", first.as_bytes(), b"\n"]);
+            }
+        });
+    }
+    let (in_use, heap_size) = super::alloc::usage();
+    print_err_fmt(format_args!("  heap: {} of {} bytes in use\n", in_use, heap_size));
+    print_frames();
+}
+
+#[cfg(feature = "backtrace")]
+fn print_frames() {
+    use crate::ptr;
+    use crate::sys_common::backtrace::{unwind_backtrace, Frame};
+
+    let mut frames = [Frame {
+        exact_position: ptr::null(),
+        symbol_addr: ptr::null(),
+        inline_context: 0,
+    }; 32];
+    if let Ok((n, _)) = unwind_backtrace(&mut frames) {
+        for frame in &frames[..n] {
+            let offset = (frame.exact_position as u64).wrapping_sub(super::abi::mem::image_base());
+            print_err_fmt(format_args!("  frame: <image>+0x{:x}\n", offset));
+        }
+    }
+}
+
+#[cfg(not(feature = "backtrace"))]
+fn print_frames() {}
+
+const PANIC_MESSAGE_LEN: usize = 256;
+
+// A message, truncated to fit a fixed-size buffer so recording it doesn't
+// allocate.
+struct Message {
+    buf: [u8; PANIC_MESSAGE_LEN],
+    len: usize,
+}
+
+impl Message {
+    const fn new() -> Message {
+        Message { buf: [0; PANIC_MESSAGE_LEN], len: 0 }
+    }
+
+    fn set(&mut self, message: Option<&fmt::Arguments<'_>>, payload: &mut dyn BoxMeUp,
+           file_line_col: &(&str, u32, u32)) {
+        let (file, line, col) = *file_line_col;
+        self.len = 0;
+        let _ = match message {
+            Some(message) => write!(self, "'{}', {}:{}:{}", message, file, line, col),
+            None => {
+                let payload = payload.get();
+                let msg = match payload.downcast_ref::<&'static str>() {
+                    Some(s) => *s,
+                    None => match payload.downcast_ref::<String>() {
+                        Some(s) => &s[..],
+                        None => "Box<Any>",
+                    }
+                };
+                write!(self, "'{}', {}:{}:{}", msg, file, line, col)
+            }
+        };
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = cmp::min(s.len(), PANIC_MESSAGE_LEN - self.len);
+        self.buf[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+thread_local! {
+    static FIRST_PANIC: RefCell<Message> = RefCell::new(Message::new());
+    static PRINTING: Cell<bool> = Cell::new(false);
+}
+
+// Serializes `print_err`, so that the output of concurrent callers doesn't
+// interleave.
+static PRINT_LOCK: SpinMutex<()> = SpinMutex::new(());
+
+// The most output written to standard error with a single usercall, which
+// bounds the size of the userspace buffer it needs.
+const PRINT_CHUNK_LEN: usize = 1024;
+
+/// Prints `msg` to the installed error output sink or standard error, unless
+/// the current thread is already doing so further up the stack.
+pub fn print_err(msg: &[u8]) {
+    print_err_parts(&[msg])
+}
+
+/// Like `print_err`, but formats the message without allocating, truncating
+/// it if it is long.
+pub fn print_err_fmt(args: fmt::Arguments<'_>) {
+    let mut msg = Message::new();
+    let _ = msg.write_fmt(args);
+    print_err(msg.as_bytes())
+}
+
+// Like `print_err`, printing `parts` together as one message.
+fn print_err_parts(parts: &[&[u8]]) {
+    let _ = PRINTING.try_with(|printing| {
+        if printing.replace(true) {
+            return;
+        }
+        let _guard = PRINT_LOCK.lock();
+        match error_output::sink() {
+            Some(sink) => {
+                for part in parts {
+                    sink(part)
+                }
+            }
+            None => {
+                if let Ok(mut stderr) = Stderr::new() {
+                    for chunk in parts.iter().flat_map(|part| part.chunks(PRINT_CHUNK_LEN)) {
+                        if io::Write::write_all(&mut stderr, chunk).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        printing.set(false);
+    });
+}
+
+/// Called for every panic, `panics` being the number of panics the current
+/// thread is processing including this one.
+pub fn panicking(panics: usize, message: Option<&fmt::Arguments<'_>>, payload: &mut dyn BoxMeUp,
+                 file_line_col: &(&str, u32, u32)) {
+    match panics {
+        1 => {
+            let _ = FIRST_PANIC.try_with(|first| {
+                if let Ok(mut first) = first.try_borrow_mut() {
+                    first.set(message, payload, file_line_col);
+                }
+            });
+        }
+        2 => {
+            let _ = FIRST_PANIC.try_with(|first| {
+                if let Ok(first) = first.try_borrow() {
+                    print_err_parts(&[b"thread panicked at ", first.as_bytes(), b"\n"]);
+                }
+            });
+            let mut second = Message::new();
+            second.set(message, payload, file_line_col);
+            print_err_parts(&[b"then panicked while panicking at ", second.as_bytes(), b"\n"]);
+        }
+        _ => {} // the panic hook panicked, don't try anything fancy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        let reasons = [
+            AbortReason::Abort,
+            AbortReason::OutOfMemory,
+            AbortReason::DoublePanic,
+            AbortReason::PanicRuntime,
+            AbortReason::RwLockMisuse,
+            AbortReason::HeapOverflow,
+            AbortReason::DoubleFree,
+            AbortReason::UsercallViolation,
+            AbortReason::Deadlock,
+            AbortReason::HardwareException,
+            AbortReason::StackOverflow,
+        ];
+        for (i, reason) in reasons.iter().enumerate() {
+            assert_eq!(reason.code(), i as u32 + 1);
+        }
+    }
+
+    #[test]
+    fn truncated_panic_message() {
+        let mut message = Message::new();
+        let long = "x".repeat(PANIC_MESSAGE_LEN + 1);
+        message.write_str(&long).unwrap();
+        assert_eq!(message.as_bytes(), &long.as_bytes()[..PANIC_MESSAGE_LEN]);
+    }
+}
@@ -1,32 +1,700 @@
 use crate::alloc::{GlobalAlloc, Layout, System};
+use crate::cmp;
+use crate::intrinsics;
+use crate::io;
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "sgx-alloc-hooks")]
+use crate::sync::atomic::AtomicPtr;
 
+#[cfg(feature = "sgx-alloc-hooks")]
+use super::abi::mem::image_base;
+use super::abi::tls::{Key, Tls};
+use super::abort::{abort_with_code, print_err_fmt, AbortReason};
+use super::ext::perf;
 use super::waitqueue::SpinMutex;
 
+extern {
+    static HEAP_SIZE: usize;
+}
+
 // Using a SpinMutex because we never want to exit the enclave waiting for the
 // allocator.
+//
+// FIXME: the heap is a fixed region of `HEAP_SIZE` bytes, which dlmalloc's
+// SGX backend hands out in one piece. On SGX2, it could instead start small
+// and be grown by `EACCEPT`ing pages added at runtime, but that needs the
+// runner to `EAUG` the pages on request. There is no usercall for that, and
+// dlmalloc would have to learn to ask for more memory than it got at first.
 #[cfg_attr(test, linkage = "available_externally")]
 #[export_name = "_ZN16__rust_internals3std3sys3sgx5alloc8DLMALLOCE"]
 static DLMALLOC: SpinMutex<dlmalloc::Dlmalloc> = SpinMutex::new(dlmalloc::DLMALLOC_INIT);
 
+// The number of bytes currently allocated, and the most that were allocated at
+// any time.
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn allocated(ptr: *mut u8, size: usize) -> *mut u8 {
+    if !ptr.is_null() {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        let mut peak = PEAK.load(Ordering::Relaxed);
+        while allocated > peak {
+            match PEAK.compare_exchange_weak(peak, allocated,
+                                             Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => peak = current,
+            }
+        }
+    }
+    ptr
+}
+
+fn deallocated(size: usize) {
+    ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+}
+
+// Small allocations are served from per-thread caches of free blocks, so that
+// threads don't contend on `DLMALLOC` for every allocation. A small
+// allocation always gets the size and alignment of its size class, whether it
+// went through a cache or not, so any block can be returned to any cache or to
+// dlmalloc directly.
+const CLASS_SIZE: usize = 16;
+const CLASSES: usize = 16;
+const MAGAZINE_LEN: usize = 32;
+
+// Returns the size class of `layout`, if it is small.
+fn size_class(layout: &Layout) -> Option<usize> {
+    if layout.align() <= CLASS_SIZE && layout.size() <= CLASS_SIZE * CLASSES {
+        Some(layout.size().saturating_sub(1) / CLASS_SIZE)
+    } else {
+        None
+    }
+}
+
+fn class_size(class: usize) -> usize {
+    (class + 1) * CLASS_SIZE
+}
+
+// Free blocks of one size class
+struct Magazine {
+    len: usize,
+    blocks: [*mut u8; MAGAZINE_LEN],
+}
+
+// All zeroes is a valid, empty cache.
+struct Cache {
+    magazines: [Magazine; CLASSES],
+    // Whether the thread is running an allocation hook
+    #[cfg(feature = "sgx-alloc-hooks")]
+    in_hook: bool,
+}
+
+// The TLS key of the caches, or 0 if it hasn't been created yet
+static CACHE_KEY: AtomicUsize = AtomicUsize::new(0);
+
+// Returns the cache of the current thread, creating it if necessary. Returns
+// null if the thread can't have a cache, because it doesn't have TLS or the
+// cache couldn't be allocated.
+unsafe fn cache() -> *mut Cache {
+    if !Tls::is_active() {
+        return ptr::null_mut();
+    }
+    let key = match CACHE_KEY.load(Ordering::Acquire) {
+        0 => {
+            let key = Tls::create(Some(flush_cache)).as_usize();
+            match CACHE_KEY.compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => key,
+                Err(other) => {
+                    Tls::destroy(Key::from_usize(key));
+                    other
+                }
+            }
+        }
+        key => key,
+    };
+    let key = Key::from_usize(key);
+    let cache = Tls::get(key) as *mut Cache;
+    if !cache.is_null() {
+        return cache;
+    }
+    let cache = DLMALLOC.lock().calloc(mem::size_of::<Cache>(), mem::align_of::<Cache>());
+    Tls::set(key, cache);
+    cache as *mut Cache
+}
+
+// Returns all blocks of the exiting thread's cache to dlmalloc.
+unsafe extern "C" fn flush_cache(cache: *mut u8) {
+    let mut dlmalloc = DLMALLOC.lock();
+    for (class, magazine) in (*(cache as *mut Cache)).magazines.iter().enumerate() {
+        for &block in &magazine.blocks[..magazine.len] {
+            dlmalloc.free(block, class_size(class), CLASS_SIZE);
+        }
+    }
+    dlmalloc.free(cache, mem::size_of::<Cache>(), mem::align_of::<Cache>());
+}
+
+unsafe fn alloc_small(class: usize) -> *mut u8 {
+    let cache = cache();
+    if cache.is_null() {
+        check_watermarks(class_size(class));
+        return DLMALLOC.lock().malloc(class_size(class), CLASS_SIZE);
+    }
+    if (*cache).magazines[class].len == 0 {
+        // Callbacks may allocate from this thread's cache, so check before
+        // borrowing the magazine
+        check_watermarks(class_size(class));
+    }
+    let magazine = &mut (*cache).magazines[class];
+    if magazine.len == 0 {
+        // Refill half of the magazine while holding the lock only once
+        let mut dlmalloc = DLMALLOC.lock();
+        while magazine.len < MAGAZINE_LEN / 2 {
+            let block = dlmalloc.malloc(class_size(class), CLASS_SIZE);
+            if block.is_null() {
+                break;
+            }
+            magazine.blocks[magazine.len] = block;
+            magazine.len += 1;
+        }
+        if magazine.len == 0 {
+            return ptr::null_mut();
+        }
+    }
+    magazine.len -= 1;
+    magazine.blocks[magazine.len]
+}
+
+unsafe fn dealloc_small(block: *mut u8, class: usize) {
+    let cache = cache();
+    if cache.is_null() {
+        DLMALLOC.lock().free(block, class_size(class), CLASS_SIZE);
+        return;
+    }
+    let magazine = &mut (*cache).magazines[class];
+    if magazine.len == MAGAZINE_LEN {
+        // Return half of the magazine, so memory doesn't accumulate in the
+        // caches of threads that free more than they allocate
+        let mut dlmalloc = DLMALLOC.lock();
+        while magazine.len > MAGAZINE_LEN / 2 {
+            magazine.len -= 1;
+            dlmalloc.free(magazine.blocks[magazine.len], class_size(class), CLASS_SIZE);
+        }
+    }
+    magazine.blocks[magazine.len] = block;
+    magazine.len += 1;
+}
+
+// Whether freed memory is overwritten with zeroes
+static ZERO_ON_FREE: AtomicBool = AtomicBool::new(cfg!(feature = "sgx-zero-on-free"));
+
+/// Sets whether memory is overwritten with zeroes when it is freed, so that
+/// secrets don't linger in the heap. This also applies to memory freed by C
+/// code through `__rust_c_dealloc`, unless another global allocator is
+/// installed with `#[global_allocator]`. Enabled by default if the standard
+/// library is built with the `sgx-zero-on-free` feature.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn set_zero_on_free(enabled: bool) {
+    ZERO_ON_FREE.store(enabled, Ordering::Relaxed);
+}
+
+// The return address of the allocation function, relative to the image base
+#[cfg(feature = "sgx-alloc-hooks")]
+#[inline(always)]
+fn caller() -> u64 {
+    perf::return_address().wrapping_sub(image_base())
+}
+
+// Overwrites memory that is about to be freed, in a way that can't be
+// optimized out.
+unsafe fn scrub(ptr: *mut u8, len: usize) {
+    intrinsics::volatile_set_memory(ptr, 0, len);
+}
+
+// With debug assertions, every block has a guard word after the allocation,
+// and freed blocks are kept in quarantine for a while before they can be
+// reused, so that overflows and double frees can be detected.
+const GUARD: u64 = 0xfdfd_fdfd_fdfd_fdfd;
+const GUARD_LEN: usize = mem::size_of::<u64>();
+const QUARANTINE_LEN: usize = 64;
+
+// The layout of the block that holds an allocation of `layout`
+fn block_layout(layout: Layout) -> Option<Layout> {
+    if cfg!(debug_assertions) {
+        let size = layout.size().checked_add(GUARD_LEN)?;
+        Some(unsafe { Layout::from_size_align_unchecked(size, layout.align()) })
+    } else {
+        Some(layout)
+    }
+}
+
+// The guard word depends on the address, so that a guard word copied along
+// with the allocation isn't valid
+fn guard_word(ptr: *mut u8) -> u64 {
+    GUARD ^ ptr as u64
+}
+
+unsafe fn set_guard(ptr: *mut u8, size: usize) {
+    if cfg!(debug_assertions) {
+        ptr::write_unaligned(ptr.add(size) as *mut u64, guard_word(ptr));
+    }
+}
+
+unsafe fn check_guard(ptr: *mut u8, size: usize) {
+    if cfg!(debug_assertions) &&
+        ptr::read_unaligned(ptr.add(size) as *const u64) != guard_word(ptr) {
+        print_err_fmt(format_args!("heap buffer overflow: memory after the allocation of {} \
+                                    bytes at {:p} was overwritten\n", size, ptr));
+        abort_with_code(AbortReason::HeapOverflow);
+    }
+}
+
+// Recently freed blocks, by address and block layout
+struct Quarantine {
+    blocks: [Option<(usize, Layout)>; QUARANTINE_LEN],
+    next: usize,
+}
+
+impl Quarantine {
+    fn contains(&self, ptr: usize) -> bool {
+        self.blocks.iter().any(|block| block.map_or(false, |(p, _)| p == ptr))
+    }
+
+    // Returns the oldest block, which is ready to be freed, if the quarantine
+    // was full.
+    fn insert(&mut self, ptr: usize, layout: Layout) -> Option<(usize, Layout)> {
+        let oldest = self.blocks[self.next].take();
+        self.blocks[self.next] = Some((ptr, layout));
+        self.next = (self.next + 1) % QUARANTINE_LEN;
+        oldest
+    }
+}
+
+static QUARANTINE: SpinMutex<Quarantine> = SpinMutex::new(Quarantine {
+    blocks: [None; QUARANTINE_LEN],
+    next: 0,
+});
+
+// Frees the block of an allocation, through the quarantine if there is one.
+unsafe fn free_block(ptr: *mut u8, block: Layout) {
+    // Scrub before the block is quarantined, it may stay there for many frees
+    if ZERO_ON_FREE.load(Ordering::Relaxed) {
+        scrub(ptr, block.size());
+    }
+    if !cfg!(debug_assertions) {
+        dealloc_block(ptr, block);
+        return;
+    }
+    let oldest = {
+        let mut quarantine = QUARANTINE.lock();
+        if quarantine.contains(ptr as usize) {
+            drop(quarantine);
+            print_err_fmt(format_args!("double free of the allocation at {:p}\n", ptr));
+            abort_with_code(AbortReason::DoubleFree);
+        }
+        quarantine.insert(ptr as usize, block)
+    };
+    if let Some((ptr, block)) = oldest {
+        dealloc_block(ptr as *mut u8, block);
+    }
+}
+
+unsafe fn alloc_block(layout: Layout) -> *mut u8 {
+    match size_class(&layout) {
+        Some(class) => alloc_small(class),
+        None => {
+            check_watermarks(layout.size());
+            DLMALLOC.lock().malloc(layout.size(), layout.align())
+        }
+    }
+}
+
+unsafe fn dealloc_block(ptr: *mut u8, layout: Layout) {
+    match size_class(&layout) {
+        Some(class) => dealloc_small(ptr, class),
+        None => DLMALLOC.lock().free(ptr, layout.size(), layout.align()),
+    }
+}
+
+/// Functions that are called for every allocation made through [`System`],
+/// for example to detect leaks or to profile allocations.
+///
+/// `caller` is the return address of the allocation function, relative to
+/// the image base. Allocations made while a hook is running on the same
+/// thread, and allocations made by a thread before or after it can use
+/// thread-local storage, don't invoke the hooks.
+///
+/// The hooks must not panic: they run in the middle of an allocation, and the
+/// enclave aborts if one unwinds.
+///
+/// [`System`]: ../../../alloc/struct.System.html
+#[cfg(feature = "sgx-alloc-hooks")]
+#[derive(Clone, Copy, Debug)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct AllocHooks {
+    /// Called after `ptr` was allocated with `layout`.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub on_alloc: fn(ptr: *mut u8, layout: Layout, caller: u64),
+    /// Called before `ptr`, which was allocated with `layout`, is
+    /// deallocated.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub on_dealloc: fn(ptr: *mut u8, layout: Layout, caller: u64),
+    /// Called after `ptr`, which was allocated with `layout`, was reallocated
+    /// to `new_ptr` with `new_size`.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub on_realloc: fn(ptr: *mut u8, layout: Layout, new_ptr: *mut u8, new_size: usize,
+                       caller: u64),
+}
+
+#[cfg(feature = "sgx-alloc-hooks")]
+static HOOKS: AtomicPtr<AllocHooks> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs `hooks` to be called for every allocation, replacing the
+/// previously installed hooks. If `hooks` is `None`, no hooks are called.
+#[cfg(feature = "sgx-alloc-hooks")]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn set_alloc_hooks(hooks: Option<&'static AllocHooks>) {
+    let hooks = hooks.map_or(ptr::null_mut(), |hooks| hooks as *const _ as *mut _);
+    HOOKS.store(hooks, Ordering::SeqCst);
+}
+
+// Calls `f` with the installed hooks and the caller, unless the current
+// thread is already running a hook. Must be inlined so that `caller` is the
+// allocation function's caller.
+#[cfg(feature = "sgx-alloc-hooks")]
+#[inline(always)]
+unsafe fn call_hook<F: FnOnce(&AllocHooks, u64)>(f: F) {
+    let hooks = HOOKS.load(Ordering::SeqCst);
+    if hooks.is_null() {
+        return;
+    }
+    let cache = cache();
+    if cache.is_null() || (*cache).in_hook {
+        return;
+    }
+    (*cache).in_hook = true;
+    let _guard = HookGuard(cache);
+    f(&*hooks, caller());
+}
+
+// Marks the hook as finished, even if it unwinds, which aborts because
+// unwinding out of the allocator is undefined behavior.
+#[cfg(feature = "sgx-alloc-hooks")]
+struct HookGuard(*mut Cache);
+
+#[cfg(feature = "sgx-alloc-hooks")]
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        unsafe { (*self.0).in_hook = false };
+        if crate::thread::panicking() {
+            rtabort!("an allocation hook panicked");
+        }
+    }
+}
+
 #[stable(feature = "alloc_system_type", since = "1.28.0")]
 unsafe impl GlobalAlloc for System {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        DLMALLOC.lock().malloc(layout.size(), layout.align())
+        perf::sample();
+        let block = match block_layout(layout) {
+            Some(block) => block,
+            None => return ptr::null_mut(),
+        };
+        let ptr = allocated(alloc_block(block), layout.size());
+        if !ptr.is_null() {
+            set_guard(ptr, layout.size());
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "sgx-alloc-hooks")]
+            call_hook(|hooks, caller| (hooks.on_alloc)(ptr, layout, caller));
+        }
+        ptr
     }
 
     #[inline]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        DLMALLOC.lock().calloc(layout.size(), layout.align())
+        perf::sample();
+        let block = match block_layout(layout) {
+            Some(block) => block,
+            None => return ptr::null_mut(),
+        };
+        let ptr = match size_class(&block) {
+            Some(class) => {
+                let ptr = alloc_small(class);
+                if !ptr.is_null() {
+                    ptr::write_bytes(ptr, 0, layout.size());
+                }
+                ptr
+            }
+            None => {
+                check_watermarks(block.size());
+                DLMALLOC.lock().calloc(block.size(), block.align())
+            }
+        };
+        let ptr = allocated(ptr, layout.size());
+        if !ptr.is_null() {
+            set_guard(ptr, layout.size());
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "sgx-alloc-hooks")]
+            call_hook(|hooks, caller| (hooks.on_alloc)(ptr, layout, caller));
+        }
+        ptr
     }
 
     #[inline]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        DLMALLOC.lock().free(ptr, layout.size(), layout.align())
+        #[cfg(feature = "sgx-alloc-hooks")]
+        call_hook(|hooks, caller| (hooks.on_dealloc)(ptr, layout, caller));
+        check_guard(ptr, layout.size());
+        // The block layout was valid when the allocation was made
+        free_block(ptr, block_layout(layout).unwrap_or(layout));
+        deallocated(layout.size());
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
     }
 
     #[inline]
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        DLMALLOC.lock().realloc(ptr, layout.size(), layout.align(), new_size)
+        perf::sample();
+        check_guard(ptr, layout.size());
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let (block, new_block) = match (block_layout(layout), block_layout(new_layout)) {
+            (Some(block), Some(new_block)) => (block, new_block),
+            _ => return ptr::null_mut(),
+        };
+        let zero_on_free = ZERO_ON_FREE.load(Ordering::Relaxed);
+        let new = match (size_class(&block), size_class(&new_block)) {
+            // dlmalloc extends the block in place if the chunk after it is
+            // free, but doesn't scrub the old block if it has to move it, and
+            // bypasses the quarantine
+            (None, None) if !zero_on_free && !cfg!(debug_assertions) => {
+                check_watermarks(new_size.saturating_sub(layout.size()));
+                DLMALLOC.lock().realloc(ptr, block.size(), block.align(), new_block.size())
+            }
+            // The block is already large enough
+            (Some(old), Some(new)) if old == new => {
+                if zero_on_free && new_block.size() < block.size() {
+                    scrub(ptr.add(new_block.size()), block.size() - new_block.size());
+                }
+                ptr
+            }
+            _ => {
+                let new = alloc_block(new_block);
+                if !new.is_null() {
+                    ptr::copy_nonoverlapping(ptr, new, cmp::min(layout.size(), new_size));
+                    free_block(ptr, block);
+                }
+                new
+            }
+        };
+        if !new.is_null() {
+            set_guard(new, new_size);
+            deallocated(layout.size());
+            #[cfg(feature = "sgx-alloc-hooks")]
+            call_hook(|hooks, caller| {
+                (hooks.on_realloc)(ptr, layout, new, new_size, caller)
+            });
+        }
+        allocated(new, new_size)
+    }
+}
+
+/// Statistics of the heap of the enclave.
+///
+/// Only allocations made through [`System`] are counted. If another global
+/// allocator is installed with `#[global_allocator]`, it only shows up here to
+/// the extent it uses `System` itself.
+///
+/// [`System`]: ../../../alloc/struct.System.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct HeapStats {
+    /// The number of bytes currently allocated.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub allocated: usize,
+    /// The size of the heap minus the allocated bytes. Not all of these
+    /// bytes may be available for allocation, the allocator doesn't track
+    /// its own overhead and fragmentation.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub free: usize,
+    /// The most bytes that were allocated at any time.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub peak: usize,
+    /// The number of successful allocations. Reallocations are not counted.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub allocations: usize,
+    /// The number of deallocations.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub deallocations: usize,
+}
+
+/// Returns statistics of the heap of the enclave. The heap size is fixed
+/// when the enclave is signed.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn heap_stats() -> HeapStats {
+    let (allocated, heap_size) = usage();
+    HeapStats {
+        allocated,
+        free: heap_size.saturating_sub(allocated),
+        peak: PEAK.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+/// The maximum number of heap watermarks that can be registered.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub const MAX_WATERMARKS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Watermark {
+    threshold: usize,
+    callback: fn(HeapStats),
+    // Whether usage is above the threshold since the callback was called
+    triggered: bool,
+}
+
+static WATERMARKS: SpinMutex<[Option<Watermark>; MAX_WATERMARKS]> =
+    SpinMutex::new([None; MAX_WATERMARKS]);
+// Whether any watermarks are registered, so that allocations don't need to
+// look at `WATERMARKS` otherwise
+static HAS_WATERMARKS: AtomicBool = AtomicBool::new(false);
+
+// The number of bytes that make up `percent` percent of the heap
+fn threshold(heap_size: usize, percent: u8) -> usize {
+    (heap_size as u128 * percent as u128 / 100) as usize
+}
+
+/// Registers `callback` to be called when the number of allocated bytes
+/// crosses `percent` percent of the heap size, for example to let caches
+/// shrink before allocations start failing.
+///
+/// The callback is called once, on the thread whose allocation crossed the
+/// watermark, before that allocation is made. It is called again only after
+/// usage has dropped below the watermark and crosses it again. Only
+/// allocations that can't be served from a thread's cache of free blocks
+/// check the watermarks, so usage may be slightly above the watermark by the
+/// time the callback is called. Allocations made by the callback don't call
+/// any callbacks.
+///
+/// The callback must not panic: it runs in the middle of an allocation, and
+/// the enclave aborts if it unwinds.
+///
+/// Returns an error if `percent` isn't between 1 and 100, or if
+/// `MAX_WATERMARKS` watermarks are already registered.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn register_heap_watermark(percent: u8, callback: fn(HeapStats)) -> io::Result<()> {
+    if percent == 0 || percent > 100 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "heap watermark must be between 1 and 100 percent"));
+    }
+    let watermark = Watermark {
+        threshold: threshold(unsafe { HEAP_SIZE }, percent),
+        callback,
+        triggered: false,
+    };
+    if let Some(slot) = WATERMARKS.lock().iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(watermark);
+        HAS_WATERMARKS.store(true, Ordering::Relaxed);
+        return Ok(());
+    }
+    Err(io::Error::new(io::ErrorKind::Other, "too many heap watermarks registered"))
+}
+
+// Calls the callbacks of the watermarks that allocating `size` more bytes
+// crosses. Called before allocations that dlmalloc has to serve.
+fn check_watermarks(size: usize) {
+    if !HAS_WATERMARKS.load(Ordering::Relaxed) {
+        return;
+    }
+    let (allocated, _) = usage();
+    let usage = allocated.saturating_add(size);
+    let mut callbacks = [None; MAX_WATERMARKS];
+    {
+        // If the lock is held, this is an allocation made while registering
+        // a watermark or by another thread checking them, skip it.
+        let mut watermarks = match WATERMARKS.try_lock() {
+            Some(watermarks) => watermarks,
+            None => return,
+        };
+        for (slot, callback) in watermarks.iter_mut().zip(callbacks.iter_mut()) {
+            if let Some(ref mut watermark) = *slot {
+                if usage < watermark.threshold {
+                    watermark.triggered = false;
+                } else if !watermark.triggered {
+                    watermark.triggered = true;
+                    *callback = Some(watermark.callback);
+                }
+            }
+        }
+    }
+    // The lock is released, so callbacks can allocate and free memory
+    if callbacks.iter().any(Option::is_some) {
+        // Unwinding out of the allocator is undefined behavior
+        let _guard = AbortOnUnwind;
+        let stats = heap_stats();
+        for callback in callbacks.iter().filter_map(|&callback| callback) {
+            callback(stats);
+        }
+    }
+}
+
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        if crate::thread::panicking() {
+            rtabort!("a heap watermark callback panicked");
+        }
+    }
+}
+
+/// Returns the number of bytes currently allocated and the size of the heap.
+pub fn usage() -> (usize, usize) {
+    (ALLOCATED.load(Ordering::Relaxed), unsafe { HEAP_SIZE })
+}
+
+/// Reports a failed allocation of `layout` and aborts.
+pub fn abort_alloc_error(layout: Layout) -> ! {
+    print_err_fmt(format_args!("memory allocation of {} bytes with alignment {} failed\n",
+                               layout.size(), layout.align()));
+    abort_with_code(AbortReason::OutOfMemory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_classes() {
+        let class = |size, align| size_class(&Layout::from_size_align(size, align).unwrap());
+        assert_eq!(class(0, 1), Some(0));
+        assert_eq!(class(16, 16), Some(0));
+        assert_eq!(class(17, 8), Some(1));
+        assert_eq!(class(CLASS_SIZE * CLASSES, 1), Some(CLASSES - 1));
+        assert_eq!(class(CLASS_SIZE * CLASSES + 1, 1), None);
+        assert_eq!(class(8, 32), None);
+    }
+
+    #[test]
+    fn watermark_threshold() {
+        assert_eq!(threshold(1000, 80), 800);
+        assert_eq!(threshold(999, 100), 999);
+        assert_eq!(threshold(usize::max_value(), 100), usize::max_value());
+    }
+
+    #[test]
+    fn quarantine() {
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let mut quarantine = Quarantine { blocks: [None; QUARANTINE_LEN], next: 0 };
+        for ptr in 1..=QUARANTINE_LEN {
+            assert_eq!(quarantine.insert(ptr, layout), None);
+        }
+        assert!(quarantine.contains(1));
+        assert_eq!(quarantine.insert(QUARANTINE_LEN + 1, layout), Some((1, layout)));
+        assert!(!quarantine.contains(1));
+        assert!(quarantine.contains(QUARANTINE_LEN + 1));
     }
 }
@@ -1,8 +1,9 @@
 use crate::io;
 use crate::error::Error;
 use crate::fmt;
+use crate::mem;
 use crate::sys_common::backtrace::Frame;
-use crate::sys::sgx::abi::mem::image_base;
+use crate::sys::sgx::abi::mem::{image_base, is_enclave_range};
 
 use unwind as uw;
 
@@ -47,7 +48,73 @@ pub fn unwind_backtrace(frames: &mut [Frame]) -> io::Result<(usize, BacktraceCon
             UnwindError(result_unwind),
         )),
     };
-    res
+    let frames = cx.frames;
+    let len = match res {
+        // The unwinder needs .eh_frame, which may be missing or incomplete.
+        // Fall back to walking the frame pointers, which the target always
+        // keeps.
+        Ok((0, _)) | Err(_) => unsafe { frame_pointer_backtrace(frames) },
+        Ok((len, _)) => len,
+    };
+    Ok((remove_entry_frames(&mut frames[..len]), BacktraceContext))
+}
+
+// The assembly code the enclave is entered through and that performs
+// usercalls isn't interesting to users. Moves all other frames to the start
+// of `frames` and returns their number.
+fn remove_entry_frames(frames: &mut [Frame]) -> usize {
+    extern "C" {
+        static entry_code_start: u8;
+        static entry_code_end: u8;
+    }
+    let (start, end) = unsafe {
+        (&entry_code_start as *const u8 as usize, &entry_code_end as *const u8 as usize)
+    };
+    let mut len = 0;
+    for idx in 0..frames.len() {
+        let ip = frames[idx].exact_position as usize;
+        if ip < start || ip >= end {
+            frames[len] = frames[idx];
+            len += 1;
+        }
+    }
+    len
+}
+
+// Follows the chain of saved frame pointers starting at the caller's frame,
+// recording the return address of each frame. Stops at the first frame
+// pointer that is null, misaligned, outside the enclave or not further up
+// the stack than the previous one.
+#[inline(never)]
+unsafe fn frame_pointer_backtrace(frames: &mut [Frame]) -> usize {
+    let mut fp: *const usize;
+    asm!("mov %rbp, $0" : "=r"(fp));
+    let mut idx = 0;
+    while idx < frames.len() {
+        if fp.is_null() || (fp as usize) % mem::align_of::<usize>() != 0 ||
+            !is_enclave_range(fp as *const u8, 2 * mem::size_of::<usize>()) {
+            break;
+        }
+        // The return address is stored just above the saved frame pointer
+        let ret = *fp.add(1);
+        if ret == 0 {
+            break;
+        }
+        // `ret` refers to the address after the calling instruction
+        let ip = (ret - 1) as *mut u8;
+        frames[idx] = Frame {
+            symbol_addr: ip,
+            exact_position: ip,
+            inline_context: 0,
+        };
+        idx += 1;
+        let next = *fp as *const usize;
+        if next <= fp {
+            break;
+        }
+        fp = next;
+    }
+    idx
 }
 
 extern "C" fn trace_fn(
@@ -79,13 +146,14 @@ extern "C" fn trace_fn(
 }
 
 // To reduce TCB size in Sgx enclave, we do not want to implement resolve_symname functionality.
-// Rather, we print the offset of the address here, which could be later mapped to correct function.
+// Rather, we print the offset of the address relative to the image base here, which could be
+// later mapped to correct function.
 pub fn resolve_symname<F>(frame: Frame,
                           callback: F,
                           _: &BacktraceContext) -> io::Result<()>
     where F: FnOnce(Option<&str>) -> io::Result<()>
 {
-    callback(Some(&format!("0x{:x}",
+    callback(Some(&format!("<image>+0x{:x}",
             (frame.symbol_addr.wrapping_offset_from(image_base() as _)))))
 }
 
@@ -0,0 +1,59 @@
+use super::ext::perf;
+use super::waitqueue::{WaitVariable, WaitQueue, SpinMutex};
+
+/// A barrier that doesn't need a mutex and condition variable.
+///
+/// The last thread to arrive releases all other threads of its generation
+/// with a single `send` usercall. Threads that arrive for the next generation
+/// before the previous one has woken up correctly wait for their own
+/// generation, since every waiting thread waits for its own queue entry to be
+/// notified.
+pub struct Barrier {
+    // The number of threads that have arrived in the current generation
+    arrived: SpinMutex<WaitVariable<usize>>,
+    num_threads: usize,
+}
+
+impl Barrier {
+    pub fn new(n: usize) -> Barrier {
+        Barrier { arrived: SpinMutex::new(WaitVariable::new(0)), num_threads: n }
+    }
+
+    pub fn wait(&self) -> bool {
+        perf::BARRIER.acquired();
+        let mut guard = self.arrived.lock();
+        let arrived = *guard.lock_var() + 1;
+        if arrived < self.num_threads {
+            *guard.lock_var_mut() = arrived;
+            WaitQueue::wait(guard, &perf::BARRIER);
+            false
+        } else {
+            *guard.lock_var_mut() = 0;
+            let _ = WaitQueue::notify_all(guard);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use crate::thread;
+
+    #[test]
+    fn generations() {
+        const N: usize = 8;
+        const GENERATIONS: usize = 10;
+
+        let barrier = Arc::new(Barrier::new(N));
+        let threads: Vec<_> = (0..N).map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                (0..GENERATIONS).filter(|_| barrier.wait()).count()
+            })
+        }).collect();
+        let leaders: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(leaders, GENERATIONS);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::sys::mutex::Mutex;
 use crate::time::Duration;
 
+use super::deadlock;
+use super::ext::perf;
 use super::waitqueue::{WaitVariable, WaitQueue, SpinMutex};
 
 pub struct Condvar {
@@ -26,14 +28,22 @@ impl Condvar {
     }
 
     pub unsafe fn wait(&self, mutex: &Mutex) {
+        deadlock::assert_held(mutex, "waited on a condition variable");
         let guard = self.inner.lock();
         mutex.unlock();
-        WaitQueue::wait(guard);
+        perf::CONDVAR.acquired();
+        WaitQueue::wait(guard, &perf::CONDVAR);
         mutex.lock()
     }
 
-    pub unsafe fn wait_timeout(&self, _mutex: &Mutex, _dur: Duration) -> bool {
-        rtabort!("timeout not supported in SGX");
+    pub unsafe fn wait_timeout(&self, mutex: &Mutex, dur: Duration) -> bool {
+        deadlock::assert_held(mutex, "waited on a condition variable");
+        let guard = self.inner.lock();
+        mutex.unlock();
+        perf::CONDVAR.acquired();
+        let success = WaitQueue::wait_timeout(guard, dur, &perf::CONDVAR);
+        mutex.lock();
+        success
     }
 
     #[inline]
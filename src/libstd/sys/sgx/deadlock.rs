@@ -0,0 +1,258 @@
+//! Lock ownership tracking for debugging deadlocks.
+//!
+//! When debug assertions are enabled, `Mutex` and the write side of `RWLock`
+//! record which thread holds them, and threads record which lock they are
+//! about to block on. Before blocking, the chain of lock owners and the locks
+//! they are blocked on is followed. If it leads back to the current thread,
+//! the enclave is aborted with a diagnostic instead of hanging forever.
+//!
+//! Locks and threads are identified by their addresses, a thread's address
+//! being that of its TCS. Ownership is not tracked while a lock is being
+//! passed from one thread to another, and the tables of owners and blocked
+//! threads have a fixed size, so not every deadlock is detected.
+
+use super::abi::thread;
+use super::abort::{abort_with_code, print_err_fmt, AbortReason};
+use super::waitqueue::SpinMutex;
+
+// How many exclusively held locks and blocked threads can be tracked. The
+// tables have a fixed size, so that tracking never allocates: the allocator,
+// or a hook called by it, may take a lock itself.
+const MAX_OWNED: usize = 512;
+const MAX_BLOCKED: usize = 256;
+// How many locks of a cycle are printed
+const PRINTED_LOCKS: usize = 8;
+
+struct State {
+    // (lock, thread) for every lock that is exclusively held, `(0, 0)` for
+    // unused entries
+    owners: [(usize, usize); MAX_OWNED],
+    // (thread, lock) for every thread that is blocked on a lock
+    blocked: [(usize, usize); MAX_BLOCKED],
+    // Set once an owner couldn't be recorded because `owners` was full
+    owners_lost: bool,
+}
+
+fn find(table: &[(usize, usize)], key: usize) -> Option<usize> {
+    table.iter().find(|&&(k, _)| k == key && k != 0).map(|&(_, v)| v)
+}
+
+// Returns `false` if the table is full.
+fn insert(table: &mut [(usize, usize)], key: usize, value: usize) -> bool {
+    match table.iter_mut().find(|&&mut (k, _)| k == 0) {
+        Some(entry) => {
+            *entry = (key, value);
+            true
+        }
+        None => false,
+    }
+}
+
+fn remove(table: &mut [(usize, usize)], key: usize) {
+    for entry in table.iter_mut().filter(|&&mut (k, _)| k == key) {
+        *entry = (0, 0);
+    }
+}
+
+impl State {
+    const fn new() -> State {
+        State { owners: [(0, 0); MAX_OWNED], blocked: [(0, 0); MAX_BLOCKED], owners_lost: false }
+    }
+
+    fn owner(&self, lock: usize) -> Option<usize> {
+        find(&self.owners, lock)
+    }
+
+    fn blocked_on(&self, tcs: usize) -> Option<usize> {
+        find(&self.blocked, tcs)
+    }
+
+    // Returns the number of `(lock, owner)` pairs that would form a cycle if
+    // `tcs` blocked on `lock`, or `0` if there is no such cycle. The first
+    // pairs of the cycle are stored in `chain`.
+    fn find_cycle(&self, tcs: usize, lock: usize, chain: &mut [(usize, usize)]) -> usize {
+        let mut lock = lock;
+        // A chain without cycles is no longer than the number of blocked
+        // threads plus one. This also bounds the search in case the other
+        // threads already form a cycle that doesn't include `tcs`.
+        for len in 0..=self.blocked.len() {
+            let owner = match self.owner(lock) {
+                Some(owner) => owner,
+                None => break,
+            };
+            if let Some(link) = chain.get_mut(len) {
+                *link = (lock, owner);
+            }
+            if owner == tcs {
+                return len + 1;
+            }
+            lock = match self.blocked_on(owner) {
+                Some(lock) => lock,
+                None => break,
+            };
+        }
+        0
+    }
+}
+
+static STATE: SpinMutex<State> = SpinMutex::new(State::new());
+
+fn current() -> usize {
+    thread::current().as_ptr() as usize
+}
+
+fn with_state<R, F: FnOnce(&mut State) -> R>(f: F) -> R {
+    f(&mut STATE.lock())
+}
+
+/// Record that the current thread now exclusively holds `lock`.
+#[inline]
+pub fn acquired<T>(lock: &T) {
+    if cfg!(debug_assertions) {
+        let lock = lock as *const T as usize;
+        with_state(|state| {
+            if !insert(&mut state.owners, lock, current()) {
+                state.owners_lost = true;
+            }
+        })
+    }
+}
+
+/// Record that `lock` is no longer exclusively held.
+#[inline]
+pub fn released<T>(lock: &T) {
+    if cfg!(debug_assertions) {
+        let lock = lock as *const T as usize;
+        with_state(|state| remove(&mut state.owners, lock))
+    }
+}
+
+/// Record that the current thread is about to block on `lock`, aborting if
+/// that would deadlock.
+#[inline]
+pub fn blocking<T>(lock: &T) {
+    if cfg!(debug_assertions) {
+        let lock = lock as *const T as usize;
+        let tcs = current();
+        let mut chain = [(0, 0); PRINTED_LOCKS];
+        let len = with_state(|state| {
+            let len = state.find_cycle(tcs, lock, &mut chain);
+            if len == 0 {
+                // Not tracking a blocked thread only hides deadlocks
+                insert(&mut state.blocked, tcs, lock);
+            }
+            len
+        });
+        // The state lock is released, so printing can't deadlock on it
+        if len == 1 {
+            print_err_fmt(format_args!("deadlock detected: TCS {:#x} tried to acquire lock \
+                                        {:#x}, which it already holds\n", tcs, lock));
+            abort_with_code(AbortReason::Deadlock);
+        } else if len > 0 {
+            print_err_fmt(format_args!("deadlock detected: TCS {:#x} is blocking on a cycle \
+                                        of {} locks:\n", tcs, len));
+            for &(lock, owner) in chain.iter().take(len) {
+                print_err_fmt(format_args!("  lock {:#x} held by TCS {:#x}\n", lock, owner));
+            }
+            if len > PRINTED_LOCKS {
+                print_err_fmt(format_args!("  ...\n"));
+            }
+            abort_with_code(AbortReason::Deadlock);
+        }
+    }
+}
+
+/// Record that the current thread is no longer blocked.
+#[inline]
+pub fn unblocked() {
+    if cfg!(debug_assertions) {
+        let tcs = current();
+        with_state(|state| remove(&mut state.blocked, tcs))
+    }
+}
+
+/// Abort if the current thread doesn't exclusively hold `lock`.
+#[inline]
+pub fn assert_held<T>(lock: &T, what: &str) {
+    if cfg!(debug_assertions) {
+        let lock = lock as *const T as usize;
+        let tcs = current();
+        let held = with_state(|state| match state.owner(lock) {
+            Some(owner) => owner == tcs,
+            // The owner may not have been recorded
+            None => state.owners_lost,
+        });
+        if !held {
+            rtabort!("TCS {:#x} {} without holding lock {:#x}", tcs, what, lock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(owners: &[(usize, usize)], blocked: &[(usize, usize)]) -> State {
+        let mut state = State::new();
+        for &(lock, tcs) in owners {
+            assert!(insert(&mut state.owners, lock, tcs));
+        }
+        for &(tcs, lock) in blocked {
+            assert!(insert(&mut state.blocked, tcs, lock));
+        }
+        state
+    }
+
+    fn cycle(state: &State, tcs: usize, lock: usize) -> Vec<(usize, usize)> {
+        let mut chain = [(0, 0); PRINTED_LOCKS];
+        let len = state.find_cycle(tcs, lock, &mut chain);
+        chain[..len].to_vec()
+    }
+
+    #[test]
+    fn find_cycle() {
+        let state = state(&[(0x10, 1), (0x20, 2), (0x30, 3)], &[(2, 0x30), (3, 0x10)]);
+        // Thread 1 holds 0x10 and blocks on it
+        assert_eq!(cycle(&state, 1, 0x10), vec![(0x10, 1)]);
+        // 1 -> 0x20 (2) -> 0x30 (3) -> 0x10 (1)
+        assert_eq!(cycle(&state, 1, 0x20), vec![(0x20, 2), (0x30, 3), (0x10, 1)]);
+        // Thread 4 is not part of any cycle
+        assert!(cycle(&state, 4, 0x20).is_empty());
+        // Unowned lock
+        assert!(cycle(&state, 1, 0x40).is_empty());
+    }
+
+    #[test]
+    fn find_cycle_among_others() {
+        // Threads 2 and 3 are deadlocked, thread 1 joining them doesn't loop
+        let state = state(&[(0x20, 2), (0x30, 3)], &[(2, 0x30), (3, 0x20)]);
+        assert!(cycle(&state, 1, 0x20).is_empty());
+    }
+
+    #[test]
+    fn long_cycle() {
+        // 1 -> 0x10 (2) -> 0x20 (3) -> ... -> 0x100 (1)
+        let n = PRINTED_LOCKS + 8;
+        let mut state = State::new();
+        for i in 1..=n {
+            insert(&mut state.owners, i * 0x10, i % n + 1);
+            insert(&mut state.blocked, i % n + 1, (i % n + 1) * 0x10);
+        }
+        remove(&mut state.blocked, 1);
+        let mut chain = [(0, 0); PRINTED_LOCKS];
+        assert_eq!(state.find_cycle(1, 0x10, &mut chain), n);
+        assert_eq!(chain[0], (0x10, 2));
+    }
+
+    #[test]
+    fn full_table() {
+        let mut owners = [(0, 0); 2];
+        assert!(insert(&mut owners, 0x10, 1));
+        assert!(insert(&mut owners, 0x20, 1));
+        assert!(!insert(&mut owners, 0x30, 1));
+        remove(&mut owners, 0x10);
+        assert!(insert(&mut owners, 0x30, 1));
+        assert_eq!(find(&owners, 0x30), Some(1));
+        assert_eq!(find(&owners, 0x10), None);
+    }
+}
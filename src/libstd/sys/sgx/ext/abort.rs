@@ -0,0 +1,46 @@
+//! Running code before the enclave aborts.
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::io;
+use crate::sync::atomic::{AtomicBool, Ordering};
+
+use super::super::waitqueue::SpinMutex;
+
+/// The maximum number of hooks that can be registered.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub const MAX_HOOKS: usize = 8;
+
+static HOOKS: SpinMutex<[Option<fn()>; MAX_HOOKS]> = SpinMutex::new([None; MAX_HOOKS]);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Registers `hook` to be run when the enclave aborts, for example to zeroize
+/// keys or flush sealed logs.
+///
+/// Hooks run in the order they were registered, on the thread that aborts
+/// the enclave, which may be in the middle of any operation. A hook should do
+/// as little as possible: it must not block, and should not allocate or
+/// acquire locks. If a hook panics or aborts, the remaining hooks are not
+/// run. Hooks run at most once, even if several threads abort concurrently.
+///
+/// Returns an error if `MAX_HOOKS` hooks are already registered.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn register_hook(hook: fn()) -> io::Result<()> {
+    if let Some(slot) = HOOKS.lock().iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(hook);
+        return Ok(());
+    }
+    Err(io::Error::new(io::ErrorKind::Other, "too many abort hooks registered"))
+}
+
+/// Runs the registered hooks, unless they are already running or have run.
+pub(crate) fn run_hooks() {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    // Don't hold the lock while running the hooks, so they can't deadlock
+    // by registering hooks.
+    let hooks = *HOOKS.lock();
+    for hook in hooks.iter().filter_map(|&hook| hook) {
+        hook();
+    }
+}
@@ -0,0 +1,85 @@
+//! Backtraces for symbolication outside the enclave.
+//!
+//! The enclave doesn't contain any symbol information, so addresses can't be
+//! resolved to function names inside it. Instead, a [`Backtrace`] records the
+//! return addresses of the stack frames relative to the image base. These can
+//! be symbolized against the enclave ELF file, for example by `addr2line`.
+//!
+//! There is no usercall to pass a backtrace to the runner. Enclaves that want
+//! their runner to symbolize backtraces can send [`Backtrace::frames`] using a
+//! user-defined usercall.
+//!
+//! This is only the enclave side of backtrace support: libstd doesn't have a
+//! platform-independent `Backtrace` type yet that this could back, and the
+//! usercall ABI is defined outside of libstd, so a usercall to send frames to
+//! the runner needs to be added there first.
+//!
+//! [`Backtrace`]: struct.Backtrace.html
+//! [`Backtrace::frames`]: struct.Backtrace.html#method.frames
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::fmt;
+use crate::ptr;
+use crate::sys_common::backtrace::{self, Frame};
+
+use super::super::abi::mem::image_base;
+
+/// Max number of frames to capture.
+const MAX_FRAMES: usize = 100;
+
+/// A captured backtrace of the current thread.
+#[derive(Clone, Debug)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct Backtrace {
+    frames: Vec<u64>,
+}
+
+impl Backtrace {
+    /// Captures a backtrace of the current thread.
+    ///
+    /// If the stack can't be walked, the backtrace is empty.
+    #[inline(never)] // so the caller appears in the backtrace
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn capture() -> Backtrace {
+        let mut frames = [Frame {
+            exact_position: ptr::null(),
+            symbol_addr: ptr::null(),
+            inline_context: 0,
+        }; MAX_FRAMES];
+        let frames = match backtrace::unwind_backtrace(&mut frames) {
+            Ok((n, _)) => frames[..n].iter()
+                .map(|frame| (frame.exact_position as u64).wrapping_sub(image_base()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Backtrace { frames }
+    }
+
+    /// Returns the addresses of the frames relative to the image base,
+    /// innermost frame first.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn frames(&self) -> &[u64] {
+        &self.frames
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, offset) in self.frames.iter().enumerate() {
+            writeln!(f, "{:4}: <image>+0x{:x}", idx, offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let bt = Backtrace { frames: vec![0x1234, 0x5678] };
+        assert_eq!(bt.to_string(), "   0: <image>+0x1234\n   1: <image>+0x5678\n");
+    }
+}
@@ -0,0 +1,42 @@
+//! Redirecting the diagnostic output of the runtime.
+//!
+//! By default, panic messages are written to the debug panic buffer in debug
+//! enclaves, and diagnostics of the unwinder and of aborts are written to
+//! standard error, which is controlled by userspace. A production enclave can
+//! instead install a [`Sink`], for example one that writes to a ring buffer in
+//! enclave memory that is sealed and flushed later.
+//!
+//! [`Sink`]: type.Sink.html
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicPtr, Ordering};
+
+/// A function receiving diagnostic output. It may be called with partial
+/// messages, and concurrently from multiple threads.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub type Sink = fn(&[u8]);
+
+static SINK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs `sink` to receive panic messages and other diagnostic output of
+/// the runtime, replacing the previously installed sink. If `sink` is
+/// `None`, the default outputs are used again.
+///
+/// When a sink is installed, panic messages are written to it even if the
+/// enclave is not a debug enclave.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn set_sink(sink: Option<Sink>) {
+    let sink = sink.map_or(ptr::null_mut(), |sink| sink as *mut ());
+    SINK.store(sink, Ordering::SeqCst);
+}
+
+pub(crate) fn sink() -> Option<Sink> {
+    let sink = SINK.load(Ordering::SeqCst);
+    if sink.is_null() {
+        None
+    } else {
+        Some(unsafe { mem::transmute(sink) })
+    }
+}
@@ -0,0 +1,220 @@
+//! Waking futures on usercall events.
+//!
+//! Events are sent to a thread with the `send` usercall, and received with
+//! the `wait` usercall. [`EventWakers`] lets an executor running on one
+//! thread register a [`Waker`] for the events a future is waiting for, and
+//! then block in a single `wait` usercall for all of them. When `wait`
+//! returns, exactly the futures waiting for the returned events are woken.
+//!
+//! For example, a future waiting for the result of a usercall submitted with
+//! [`AsyncQueues`] registers for `EV_RETURNQ_NOT_EMPTY`, and a future waiting
+//! for a message from another enclave thread registers for an event bit
+//! claimed with [`EventBit::claim`] that the other thread sends.
+//!
+//! The event bits that aren't defined by the usercall ABI are handed out by
+//! [`EventBit::claim`], so that libraries using events don't collide with
+//! each other or with the standard library.
+//!
+//! [`EventWakers`]: struct.EventWakers.html
+//! [`EventBit::claim`]: struct.EventBit.html#method.claim
+//! [`Waker`]: ../../../task/struct.Waker.html
+//! [`AsyncQueues`]: ../usercalls/async_queues/struct.AsyncQueues.html
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::fmt;
+use crate::io;
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::task::Waker;
+
+use fortanix_sgx_abi::{EV_RETURNQ_NOT_EMPTY, EV_UNPARK, EV_USERCALLQ_NOT_FULL};
+
+use super::super::abi::usercalls;
+
+// Event bits in use, starting with the ones defined by the usercall ABI
+static CLAIMED: AtomicU64 = AtomicU64::new(EV_USERCALLQ_NOT_FULL | EV_RETURNQ_NOT_EMPTY |
+                                           EV_UNPARK);
+
+/// An event bit claimed for exclusive use. The bit is released when this
+/// value is dropped.
+///
+/// Send the event with the `send` usercall, and wait for it with the `wait`
+/// usercall or [`EventWakers`](struct.EventWakers.html).
+// FIXME: The usercall ABI doesn't say whether userspace must deliver event
+// bits it doesn't define. A runner that rejects them makes `send` fail with
+// `InvalidInput`.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+#[derive(Debug, PartialEq, Eq)]
+pub struct EventBit {
+    bit: u64,
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl EventBit {
+    /// Claims an event bit that isn't used by anyone else. Returns an error if
+    /// all event bits are in use.
+    pub fn claim() -> io::Result<EventBit> {
+        let mut claimed = CLAIMED.load(Ordering::Relaxed);
+        loop {
+            if claimed == !0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "all event bits are in use"));
+            }
+            // The lowest bit that is clear
+            let bit = !claimed & claimed.wrapping_add(1);
+            match CLAIMED.compare_exchange_weak(claimed, claimed | bit,
+                                                Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return Ok(EventBit { bit }),
+                Err(actual) => claimed = actual,
+            }
+        }
+    }
+
+    /// Returns the event set consisting of just this bit.
+    pub fn event_set(&self) -> u64 {
+        self.bit
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl Drop for EventBit {
+    fn drop(&mut self) {
+        CLAIMED.fetch_and(!self.bit, Ordering::Relaxed);
+    }
+}
+
+/// The wakers of the futures an executor thread is waiting for, by event.
+///
+/// Events are only delivered to the thread they were sent to, so this should
+/// be used by the thread that waits for the events.
+// FIXME: Userspace doesn't send events for file descriptors, so futures
+// waiting for I/O on a file descriptor can't be woken this way. That needs a
+// per-fd readiness event in the usercall ABI.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+#[derive(Default)]
+pub struct EventWakers {
+    wakers: Vec<(u64, Waker)>,
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl EventWakers {
+    /// Creates an empty set of wakers.
+    pub fn new() -> EventWakers {
+        EventWakers { wakers: Vec::new() }
+    }
+
+    /// Registers `waker` to be woken when any of the events in `events` is
+    /// received.
+    ///
+    /// Registering a waker that would wake the same task as an already
+    /// registered waker adds `events` to that waker's events.
+    pub fn register(&mut self, events: u64, waker: &Waker) {
+        if events == 0 {
+            return;
+        }
+        match self.wakers.iter_mut().find(|(_, w)| w.will_wake(waker)) {
+            Some(entry) => entry.0 |= events,
+            None => self.wakers.push((events, waker.clone())),
+        }
+    }
+
+    /// Returns the union of the events registered wakers are waiting for.
+    pub fn event_mask(&self) -> u64 {
+        self.wakers.iter().fold(0, |mask, &(events, _)| mask | events)
+    }
+
+    /// Returns `true` if no wakers are registered.
+    pub fn is_empty(&self) -> bool {
+        self.wakers.is_empty()
+    }
+
+    /// Wakes and unregisters all wakers waiting for any of the events in
+    /// `eventset`. Returns the number of wakers woken.
+    pub fn wake(&mut self, eventset: u64) -> usize {
+        let mut woken = 0;
+        let mut i = 0;
+        while i < self.wakers.len() {
+            if self.wakers[i].0 & eventset != 0 {
+                self.wakers.swap_remove(i).1.wake();
+                woken += 1;
+            } else {
+                i += 1;
+            }
+        }
+        woken
+    }
+
+    /// Waits for any of the registered events with the `wait` usercall, and
+    /// wakes the wakers waiting for the events that were received. Returns
+    /// the number of wakers woken.
+    ///
+    /// `timeout` is passed to the usercall, so `WAIT_NO` polls for events and
+    /// `WAIT_INDEFINITE` blocks until an event arrives. A timeout is returned
+    /// as an error of kind `WouldBlock` or `TimedOut`.
+    pub fn wait(&mut self, timeout: u64) -> io::Result<usize> {
+        let eventset = usercalls::wait(self.event_mask(), timeout)?;
+        Ok(self.wake(eventset))
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl fmt::Debug for EventWakers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventWakers")
+            .field("wakers", &self.wakers.len())
+            .field("event_mask", &self.event_mask())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+    use crate::task::{RawWaker, RawWakerVTable};
+
+    // A waker that counts how often it was woken.
+    fn counting_waker(count: &Arc<AtomicUsize>) -> Waker {
+        unsafe fn clone(data: *const ()) -> RawWaker {
+            let count = Arc::from_raw(data as *const AtomicUsize);
+            let clone = count.clone();
+            crate::mem::forget(count);
+            RawWaker::new(Arc::into_raw(clone) as *const (), &VTABLE)
+        }
+        unsafe fn wake(data: *const ()) {
+            (*(data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+        }
+        unsafe fn drop_waker(data: *const ()) {
+            drop(Arc::from_raw(data as *const AtomicUsize));
+        }
+        static VTABLE: RawWakerVTable = RawWakerVTable { clone, wake, drop: drop_waker };
+        let raw = RawWaker::new(Arc::into_raw(count.clone()) as *const (), &VTABLE);
+        unsafe { Waker::new_unchecked(raw) }
+    }
+
+    #[test]
+    fn claim_and_release() {
+        let a = EventBit::claim().unwrap();
+        let b = EventBit::claim().unwrap();
+        assert_eq!(a.event_set().count_ones(), 1);
+        assert_eq!(a.event_set() & (EV_USERCALLQ_NOT_FULL | EV_RETURNQ_NOT_EMPTY | EV_UNPARK), 0);
+        assert_ne!(a.event_set(), b.event_set());
+        let bit = b.event_set();
+        drop(b);
+        assert_eq!(CLAIMED.load(Ordering::SeqCst) & bit, 0);
+    }
+
+    #[test]
+    fn wake_matching() {
+        let (a, b) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let mut wakers = EventWakers::new();
+        wakers.register(0b01, &counting_waker(&a));
+        wakers.register(0b10, &counting_waker(&b));
+        assert_eq!(wakers.event_mask(), 0b11);
+        assert_eq!(wakers.wake(0b10), 1);
+        assert_eq!((a.load(Ordering::SeqCst), b.load(Ordering::SeqCst)), (0, 1));
+        assert_eq!(wakers.event_mask(), 0b01);
+        assert_eq!(wakers.wake(0b11), 1);
+        assert_eq!((a.load(Ordering::SeqCst), b.load(Ordering::SeqCst)), (1, 1));
+        assert!(wakers.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+//! Handling hardware exceptions in enclave code.
+//!
+//! By default, invalid opcodes (other than `ud2`) in Rust code are turned into
+//! panics, and general protection faults and page faults abort the enclave. A
+//! [`Handler`] can be installed to handle these exceptions first, for example
+//! to emulate instructions that aren't available in an enclave, like `cpuid`.
+//!
+//! The handler sees the registers of the interrupted thread, can change them,
+//! and decides whether the thread resumes, skips the faulting instruction,
+//! aborts, or whether the exception is handled like without a handler.
+//!
+//! Keep in mind that userspace can make page faults and general protection
+//! faults happen at any memory access, so they don't necessarily indicate a
+//! bug in the enclave. A handler must not resume a thread based on the
+//! assumption that the fault was caused by the enclave itself.
+//!
+//! Exceptions are only reported to the enclave if the post-linker supports
+//! it, see `abi::exception`. Otherwise, any exception aborts the enclave.
+//!
+//! [`Handler`]: type.Handler.html
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicPtr, Ordering};
+
+/// An exception that is passed to the handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub enum Exception {
+    /// Invalid opcode (#UD).
+    InvalidOpcode,
+    /// General protection fault (#GP).
+    GeneralProtection,
+    /// Page fault (#PF).
+    PageFault,
+}
+
+/// The general purpose registers of the interrupted thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+#[allow(missing_docs)]
+pub struct Registers {
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rbx: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    /// Only the status flags (CF, PF, AF, ZF, SF, OF) and DF can be changed.
+    pub rflags: u64,
+    /// The address of the faulting instruction.
+    pub rip: u64,
+}
+
+/// The state of the interrupted thread when an exception happened.
+#[derive(Debug)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct Context {
+    exception: Exception,
+    error_code: Option<u32>,
+    address: Option<u64>,
+    registers: Registers,
+}
+
+impl Context {
+    pub(crate) fn new(exception: Exception, error_code: Option<u32>, address: Option<u64>,
+                      registers: Registers) -> Context {
+        Context { exception, error_code, address, registers }
+    }
+
+    /// Returns the exception that happened.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn exception(&self) -> Exception {
+        self.exception
+    }
+
+    /// Returns the error code of a page fault or general protection fault.
+    ///
+    /// This is only available if the enclave was built with
+    /// MISCSELECT.EXINFO.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn error_code(&self) -> Option<u32> {
+        self.error_code
+    }
+
+    /// Returns the address whose access caused a page fault.
+    ///
+    /// This is only available if the enclave was built with
+    /// MISCSELECT.EXINFO.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn fault_address(&self) -> Option<u64> {
+        self.address
+    }
+
+    /// Returns the registers of the interrupted thread.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Returns the registers of the interrupted thread for changing them.
+    /// The changes take effect if the handler returns [`Action::Resume`] or
+    /// [`Action::Skip`].
+    ///
+    /// [`Action::Resume`]: enum.Action.html#variant.Resume
+    /// [`Action::Skip`]: enum.Action.html#variant.Skip
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub(crate) fn into_registers(self) -> Registers {
+        self.registers
+    }
+}
+
+/// What happens to the interrupted thread after the handler returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub enum Action {
+    /// Resume the thread with the registers in the context, at the faulting
+    /// instruction unless `rip` was changed.
+    Resume,
+    /// Resume the thread with the registers in the context, skipping the
+    /// given number of bytes from `rip`. This is the length of the faulting
+    /// instruction, to continue after it.
+    Skip(u64),
+    /// Abort the enclave.
+    Abort,
+    /// Handle the exception like when no handler is installed.
+    Unhandled,
+}
+
+/// A function handling hardware exceptions. It runs on the interrupted
+/// thread, below the stack pointer of the interrupted code, and may be called
+/// concurrently from multiple threads.
+///
+/// The handler must not panic: the enclave aborts if it does. It also
+/// shouldn't take locks that the interrupted code might hold, since the
+/// interrupted code can't continue until the handler returns.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub type Handler = fn(&mut Context) -> Action;
+
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs `handler` to handle hardware exceptions, replacing the
+/// previously installed handler. If `handler` is `None`, exceptions are
+/// handled by the default handling again.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn set_handler(handler: Option<Handler>) {
+    let handler = handler.map_or(ptr::null_mut(), |handler| handler as *mut ());
+    HANDLER.store(handler, Ordering::SeqCst);
+}
+
+pub(crate) fn handler() -> Option<Handler> {
+    let handler = HANDLER.load(Ordering::SeqCst);
+    if handler.is_null() {
+        None
+    } else {
+        Some(unsafe { mem::transmute(handler) })
+    }
+}
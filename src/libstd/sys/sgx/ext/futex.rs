@@ -0,0 +1,150 @@
+//! Futex-like waiting on atomic integers.
+//!
+//! These functions allow building synchronization primitives outside of the
+//! standard library that block using the `wait` and `send` usercalls, instead
+//! of spinning.
+//!
+//! Waiting threads are kept in a fixed number of buckets, selected by the
+//! address of the atomic integer. If threads wait on different addresses
+//! that share a bucket, waking one of those addresses wakes all threads in
+//! the bucket. Like with futexes, callers must be prepared for spurious
+//! wakeups anyway.
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::sync::atomic::{AtomicUsize, Ordering};
+use crate::time::Duration;
+
+use super::perf;
+use super::super::waitqueue::{NotifiedTcs, SpinMutex, WaitQueue, WaitVariable};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Waiters {
+    /// No waiters
+    Empty,
+    /// All waiters wait on this address
+    Single(usize),
+    /// Waiters wait on different addresses
+    Mixed,
+}
+
+type Bucket = SpinMutex<WaitVariable<Waiters>>;
+
+const BUCKET_INIT: Bucket = SpinMutex::new(WaitVariable::new(Waiters::Empty));
+const BUCKET_COUNT: usize = 16;
+static BUCKETS: [Bucket; BUCKET_COUNT] = [
+    BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT,
+    BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT, BUCKET_INIT,
+    BUCKET_INIT, BUCKET_INIT,
+];
+
+fn bucket(addr: usize) -> &'static Bucket {
+    // The low bits are mostly zero due to alignment
+    &BUCKETS[(addr >> 3) % BUCKET_COUNT]
+}
+
+/// Blocks the current thread as long as `atomic` holds `expected`, until it
+/// is woken up by [`wake`] or `timeout` has elapsed.
+///
+/// Checking the value of `atomic` and blocking happen atomically with respect
+/// to [`wake`]. This function may also return spuriously. Returns `false` if
+/// it returned because the timeout elapsed, `true` otherwise.
+///
+/// [`wake`]: fn.wake.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn wait_on(atomic: &AtomicUsize, expected: usize, timeout: Option<Duration>) -> bool {
+    let addr = atomic as *const AtomicUsize as usize;
+    let mut guard = bucket(addr).lock();
+    if atomic.load(Ordering::SeqCst) != expected {
+        return true;
+    }
+    let waiters = if guard.queue_empty() {
+        Waiters::Single(addr)
+    } else if *guard.lock_var() == Waiters::Single(addr) {
+        Waiters::Single(addr)
+    } else {
+        Waiters::Mixed
+    };
+    *guard.lock_var_mut() = waiters;
+    match timeout {
+        None => {
+            WaitQueue::wait(guard, &perf::FUTEX);
+            true
+        }
+        Some(timeout) => WaitQueue::wait_timeout(guard, timeout, &perf::FUTEX),
+    }
+}
+
+/// Wakes up at most `n` threads blocked in [`wait_on`] on `atomic`.
+///
+/// The value of `atomic` should be updated before calling this function.
+/// Returns the number of threads woken up, which may include threads waiting
+/// on other addresses, see the [module documentation](index.html).
+///
+/// [`wait_on`]: fn.wait_on.html
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn wake(atomic: &AtomicUsize, n: usize) -> usize {
+    let addr = atomic as *const AtomicUsize as usize;
+    let bucket = bucket(addr);
+    let mut woken = 0;
+    while woken < n {
+        let guard = bucket.lock();
+        match *guard.lock_var() {
+            Waiters::Single(a) if a == addr => {
+                match WaitQueue::notify_one(guard) {
+                    Ok(mut guard) => {
+                        woken += 1;
+                        if guard.queue_empty() {
+                            *guard.lock_var_mut() = Waiters::Empty;
+                        }
+                    }
+                    Err(mut guard) => {
+                        *guard.lock_var_mut() = Waiters::Empty;
+                        break;
+                    }
+                }
+            }
+            Waiters::Mixed => {
+                if let Ok(mut guard) = WaitQueue::notify_all(guard) {
+                    if let NotifiedTcs::All { count } = guard.notified_tcs() {
+                        woken += count.get();
+                    } else {
+                        unreachable!() // called notify_all
+                    }
+                    *guard.lock_var_mut() = Waiters::Empty;
+                }
+                break;
+            }
+            _ => break, // nobody waiting on this address
+        }
+    }
+    woken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use crate::thread;
+
+    #[test]
+    fn wait_on_changed_value() {
+        let atomic = AtomicUsize::new(1);
+        assert!(wait_on(&atomic, 0, None));
+    }
+
+    #[test]
+    fn wake_up() {
+        let atomic = Arc::new(AtomicUsize::new(0));
+        let t = {
+            let atomic = atomic.clone();
+            thread::spawn(move || {
+                while atomic.load(Ordering::SeqCst) == 0 {
+                    wait_on(&atomic, 0, None);
+                }
+            })
+        };
+        atomic.store(1, Ordering::SeqCst);
+        wake(&atomic, 1);
+        t.join().unwrap();
+    }
+}
@@ -2,12 +2,32 @@
 //!
 //! SGX file descriptors behave differently from Unix file descriptors. See the
 //! description of [`TryIntoRawFd`](trait.TryIntoRawFd.html) for more details.
+//!
+//! File descriptors are the numbers userspace uses to identify streams in
+//! usercalls. A file descriptor handed to the enclave by the runner, for
+//! example in an environment variable, can be wrapped in a standard library
+//! type with [`FromRawFd`](trait.FromRawFd.html), or used directly with the
+//! usercalls in `usercalls::raw`.
 #![unstable(feature = "sgx_platform", issue = "56975")]
 
 pub use crate::sys::abi::usercalls::raw::Fd as RawFd;
+use crate::io;
 use crate::net;
+use crate::sys::abi::usercalls;
 use crate::sys::{self, AsInner, FromInner, IntoInner, TryIntoInner};
 
+use fortanix_sgx_abi::{FD_STDERR, FD_STDIN, FD_STDOUT};
+
+// FIXME: There is no way to wait for readiness of several file descriptors
+// at once, so a server needs a thread per connection that blocks in `read` or
+// `accept_stream`. A poll-like `WaitSet` needs a usercall that takes a list
+// of file descriptors and interests and returns the ready ones, or per-fd
+// readiness events for the `wait` usercall (see `ext::event`). Neither is in
+// the usercall ABI yet. The same goes for a readiness-based selector that
+// mio-style polling libraries could build on: without readiness reports from
+// userspace, it could only emulate readiness with a thread per registered
+// file descriptor blocked in `read`, which is what it's supposed to avoid.
+
 /// A trait to extract the raw SGX file descriptor from an underlying
 /// object.
 #[unstable(feature = "sgx_platform", issue = "56975")]
@@ -19,6 +39,15 @@ pub trait AsRawFd {
     /// the original object has not yet been destroyed.
     #[unstable(feature = "sgx_platform", issue = "56975")]
     fn as_raw_fd(&self) -> RawFd;
+
+    // Takes the data that was already read into the enclave but not returned
+    // by a read yet, such as data peeked from a `TcpStream`. `copy` writes it
+    // out before it reads from the file descriptor.
+    #[doc(hidden)]
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    fn take_buffered(&self) -> Vec<u8> {
+        Vec::new()
+    }
 }
 
 /// A trait to express the ability to construct an object from a raw file
@@ -61,12 +90,38 @@ pub trait TryIntoRawFd: Sized {
 
 impl AsRawFd for net::TcpStream {
     fn as_raw_fd(&self) -> RawFd { *self.as_inner().as_inner().as_inner().as_inner() }
+
+    fn take_buffered(&self) -> Vec<u8> { self.as_inner().as_inner().take_peeked() }
 }
 
 impl AsRawFd for net::TcpListener {
     fn as_raw_fd(&self) -> RawFd { *self.as_inner().as_inner().as_inner().as_inner() }
 }
 
+impl AsRawFd for io::Stdin {
+    fn as_raw_fd(&self) -> RawFd { FD_STDIN }
+}
+
+impl AsRawFd for io::Stdout {
+    fn as_raw_fd(&self) -> RawFd { FD_STDOUT }
+}
+
+impl AsRawFd for io::Stderr {
+    fn as_raw_fd(&self) -> RawFd { FD_STDERR }
+}
+
+impl<'a> AsRawFd for io::StdinLock<'a> {
+    fn as_raw_fd(&self) -> RawFd { FD_STDIN }
+}
+
+impl<'a> AsRawFd for io::StdoutLock<'a> {
+    fn as_raw_fd(&self) -> RawFd { FD_STDOUT }
+}
+
+impl<'a> AsRawFd for io::StderrLock<'a> {
+    fn as_raw_fd(&self) -> RawFd { FD_STDERR }
+}
+
 impl FromRawFd for net::TcpStream {
     unsafe fn from_raw_fd(fd: RawFd) -> net::TcpStream {
         let fd = sys::fd::FileDesc::from_inner(fd);
@@ -107,3 +162,33 @@ impl TryIntoRawFd for net::TcpListener {
         }
     }
 }
+
+/// Copies all data from `reader` to `writer`, like [`io::copy`], returning
+/// the number of bytes copied.
+///
+/// The data is staged in user memory, so it isn't copied into the enclave
+/// and back out. Use this to forward data between streams that doesn't need
+/// to be looked at by the enclave, such as encrypted traffic. Data that was
+/// already read into the enclave, for example by [`TcpStream::peek`], is
+/// written out first.
+///
+/// [`io::copy`]: ../../../io/fn.copy.html
+/// [`TcpStream::peek`]: ../../../net/struct.TcpStream.html#method.peek
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn copy<R: AsRawFd + ?Sized, W: AsRawFd + ?Sized>(reader: &R, writer: &W) -> io::Result<u64> {
+    let buffered = reader.take_buffered();
+    let mut written = 0;
+    while written < buffered.len() {
+        match usercalls::write(writer.as_raw_fd(), &buffered[written..]) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                          "failed to write whole buffer"));
+            }
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let copied = usercalls::copy(reader.as_raw_fd(), writer.as_raw_fd())?;
+    Ok(buffered.len() as u64 + copied)
+}
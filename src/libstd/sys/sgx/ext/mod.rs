@@ -1,5 +1,15 @@
 #![unstable(feature = "sgx_platform", issue = "56975")]
 
+pub mod abort;
 pub mod arch;
+#[cfg(feature = "backtrace")]
+pub mod backtrace;
+pub mod error_output;
+pub mod event;
+pub mod exceptions;
 pub mod io;
 pub mod ffi;
+pub mod futex;
+pub mod net;
+pub mod perf;
+pub mod unwind;
@@ -0,0 +1,220 @@
+//! SGX-specific extensions to networking primitives.
+//!
+//! Userspace reports the addresses of streams as strings. They are usually
+//! IP socket addresses, but a runner may also connect streams to other
+//! transports and name them however it likes. `local_addr` and `peer_addr`
+//! of the standard library types return an error for such addresses, which
+//! carries the string. The traits in this module return the strings
+//! directly.
+//!
+//! [`LocalStream`] and [`LocalListener`] are for streams that aren't TCP
+//! connections, but named services provided by the runner. What names are
+//! valid is up to the runner.
+//!
+//! [`LocalStream`]: struct.LocalStream.html
+//! [`LocalListener`]: struct.LocalListener.html
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::io::{self, IoVec, IoVecMut, Read, Write};
+use crate::net;
+use crate::sys::{self, AsInner, FromInner};
+
+use super::io::{AsRawFd, FromRawFd, RawFd, TryIntoRawFd};
+
+/// SGX-specific extensions to [`TcpStream`](../../../net/struct.TcpStream.html).
+// FIXME: A runner behind a proxy could tell the enclave the original
+// destination or a trusted label of the peer of an accepted stream, but
+// `accept_stream` only returns the two address strings. Exposing such
+// metadata here needs another user buffer in the results of `accept_stream`.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub trait TcpStreamExt {
+    /// Returns the local address of the stream as reported by userspace, or
+    /// `None` if it isn't known, such as for streams created from a raw file
+    /// descriptor.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    fn local_addr_str(&self) -> Option<&str>;
+
+    /// Returns the address of the peer as reported by userspace, or `None`
+    /// if it isn't known.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    fn peer_addr_str(&self) -> Option<&str>;
+
+    // FIXME: Keepalive settings belong here, but like the other socket
+    // options (see `sys::net::TcpStream::set_nodelay`), they need usercalls
+    // to set and get options of a stream.
+
+    // FIXME: Enclaves enforcing mutual TLS policies behind a runner that
+    // terminates TLS need a getter here for what the runner knows about the
+    // connection: whether TLS was used, the negotiated ALPN protocol and a
+    // digest of the client certificate. Like the metadata above, the runner
+    // can only pass it in another user buffer in the results of
+    // `accept_stream`, which the usercall ABI doesn't have.
+
+    // FIXME: Throughput-sensitive services need `set_recv_buffer_size` and
+    // `set_send_buffer_size` here, to hint how much userspace buffers for the
+    // stream. They need the same usercalls to set and get options of a stream
+    // as the other socket options. `usercalls::copy` could then size its
+    // staging buffer to match.
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl TcpStreamExt for net::TcpStream {
+    fn local_addr_str(&self) -> Option<&str> {
+        self.as_inner().local_addr_str()
+    }
+
+    fn peer_addr_str(&self) -> Option<&str> {
+        self.as_inner().peer_addr_str()
+    }
+}
+
+/// SGX-specific extensions to
+/// [`TcpListener`](../../../net/struct.TcpListener.html).
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub trait TcpListenerExt {
+    /// Returns the local address of the listener as reported by userspace,
+    /// or `None` if it isn't known.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    fn local_addr_str(&self) -> Option<&str>;
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl TcpListenerExt for net::TcpListener {
+    fn local_addr_str(&self) -> Option<&str> {
+        self.as_inner().local_addr_str()
+    }
+}
+
+/// A byte stream to a named service provided by the runner.
+///
+/// Streams are closed when dropped.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+#[derive(Debug)]
+pub struct LocalStream(net::TcpStream);
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl LocalStream {
+    /// Connects to the service `name` with the `connect_stream` usercall.
+    pub fn connect(name: &str) -> io::Result<LocalStream> {
+        let stream = sys::net::TcpStream::connect_str(name)?;
+        Ok(LocalStream(net::TcpStream::from_inner(stream)))
+    }
+
+    /// Returns the local name of the stream as reported by userspace, or
+    /// `None` if it isn't known.
+    pub fn local_name(&self) -> Option<&str> {
+        self.0.local_addr_str()
+    }
+
+    /// Returns the name of the peer as reported by userspace, or `None` if
+    /// it isn't known.
+    pub fn peer_name(&self) -> Option<&str> {
+        self.0.peer_addr_str()
+    }
+
+    /// Creates another handle to the same stream. The stream is closed when
+    /// all handles are dropped.
+    pub fn try_clone(&self) -> io::Result<LocalStream> {
+        self.0.try_clone().map(LocalStream)
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl Read for LocalStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoVecMut<'_>]) -> io::Result<usize> {
+        self.0.read_vectored(bufs)
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl Write for LocalStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoVec<'_>]) -> io::Result<usize> {
+        self.0.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl AsRawFd for LocalStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl FromRawFd for LocalStream {
+    unsafe fn from_raw_fd(fd: RawFd) -> LocalStream {
+        LocalStream(net::TcpStream::from_raw_fd(fd))
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl TryIntoRawFd for LocalStream {
+    fn try_into_raw_fd(self) -> Result<RawFd, LocalStream> {
+        self.0.try_into_raw_fd().map_err(LocalStream)
+    }
+}
+
+/// A listener for connections to a named service provided by the runner.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+#[derive(Debug)]
+pub struct LocalListener(net::TcpListener);
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl LocalListener {
+    /// Starts listening for connections to the service `name` with the
+    /// `bind_stream` usercall.
+    pub fn bind(name: &str) -> io::Result<LocalListener> {
+        let listener = sys::net::TcpListener::bind_str(name)?;
+        Ok(LocalListener(net::TcpListener::from_inner(listener)))
+    }
+
+    /// Waits for a connection with the `accept_stream` usercall.
+    pub fn accept(&self) -> io::Result<LocalStream> {
+        self.0.accept().map(|(stream, _)| LocalStream(stream))
+    }
+
+    /// Returns the local name of the listener as reported by userspace, or
+    /// `None` if it isn't known.
+    pub fn local_name(&self) -> Option<&str> {
+        self.0.local_addr_str()
+    }
+
+    /// Creates another handle to the same listener. The listener is closed
+    /// when all handles are dropped.
+    pub fn try_clone(&self) -> io::Result<LocalListener> {
+        self.0.try_clone().map(LocalListener)
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl AsRawFd for LocalListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl FromRawFd for LocalListener {
+    unsafe fn from_raw_fd(fd: RawFd) -> LocalListener {
+        LocalListener(net::TcpListener::from_raw_fd(fd))
+    }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl TryIntoRawFd for LocalListener {
+    fn try_into_raw_fd(self) -> Result<RawFd, LocalListener> {
+        self.0.try_into_raw_fd().map_err(LocalListener)
+    }
+}
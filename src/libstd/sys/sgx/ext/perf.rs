@@ -0,0 +1,431 @@
+//! Lock contention statistics and sampling.
+//!
+//! When the standard library is built with the `sgx-lock-stats` feature, the
+//! synchronization primitives count how often they are acquired, how often
+//! that required waiting for another thread, and how many `wait` usercalls
+//! were issued while waiting. All instances of a primitive are counted
+//! together. Measuring the time spent waiting uses the `insecure_time`
+//! usercall, so the numbers are only as trustworthy as userspace is.
+//!
+//! Without the feature, no statistics are collected and all counters read as
+//! zero.
+//!
+//! There are no signals inside an enclave, so it can't be profiled by
+//! interrupting it. Instead, once [`start_sampling`] has been called, the
+//! runtime samples the code calling it at usercalls and allocations, at most
+//! once per sampling period.
+//!
+//! With the `sgx-usercall-stats` feature, every thread also counts the
+//! usercalls it makes and the time-stamp counter ticks spent in them, by
+//! type of usercall, see [`usercall_stats`].
+//!
+//! SGX1 processors don't allow reading the time-stamp counter inside
+//! enclaves, so it is only read if the standard library is built with the
+//! `sgx-rdtsc` feature, which requires SGX2. Without it, usercall statistics
+//! only count usercalls, and the sampling period is counted in sampling
+//! points instead of ticks.
+//!
+//! [`start_sampling`]: fn.start_sampling.html
+//! [`usercall_stats`]: fn.usercall_stats.html
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+#[cfg(feature = "sgx-usercall-stats")]
+use crate::cell::RefCell;
+use crate::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::time::Duration;
+#[cfg(feature = "sgx-lock-stats")]
+use crate::time::Instant;
+
+use super::super::abi::mem::image_base;
+use super::super::time::rdtsc;
+#[cfg(feature = "sgx-usercall-stats")]
+use super::super::abi::tls::Tls;
+use super::super::waitqueue::SpinMutex;
+
+/// Contention statistics of one kind of synchronization primitive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct LockStats {
+    /// How often a lock was acquired, or a condition variable or barrier
+    /// waited on.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub acquisitions: u64,
+    /// How many of the acquisitions had to wait for another thread.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub contended_acquisitions: u64,
+    /// How many `wait` usercalls were issued while waiting.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub wait_usercalls: u64,
+    /// The total time spent waiting.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub wait_time: Duration,
+}
+
+pub(crate) struct Counters {
+    #[cfg(feature = "sgx-lock-stats")]
+    acquisitions: AtomicU64,
+    #[cfg(feature = "sgx-lock-stats")]
+    contended_acquisitions: AtomicU64,
+    #[cfg(feature = "sgx-lock-stats")]
+    wait_usercalls: AtomicU64,
+    #[cfg(feature = "sgx-lock-stats")]
+    wait_nanos: AtomicU64,
+}
+
+impl Counters {
+    #[cfg(feature = "sgx-lock-stats")]
+    pub(crate) const fn new() -> Counters {
+        Counters {
+            acquisitions: AtomicU64::new(0),
+            contended_acquisitions: AtomicU64::new(0),
+            wait_usercalls: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+        }
+    }
+
+    #[cfg(not(feature = "sgx-lock-stats"))]
+    pub(crate) const fn new() -> Counters {
+        Counters {}
+    }
+
+    /// Records a successful acquisition.
+    #[inline]
+    pub(crate) fn acquired(&self) {
+        #[cfg(feature = "sgx-lock-stats")]
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that `wait_usercalls` wait usercalls were issued.
+    #[inline]
+    pub(crate) fn wait_usercalls(&self, _wait_usercalls: u64) {
+        #[cfg(feature = "sgx-lock-stats")]
+        self.wait_usercalls.fetch_add(_wait_usercalls, Ordering::Relaxed);
+    }
+
+    /// Runs `f`, which waits for the primitive, recording it as contended.
+    #[cfg(feature = "sgx-lock-stats")]
+    #[inline]
+    pub(crate) fn contended<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        self.contended_acquisitions.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let ret = f();
+        let nanos = start.elapsed().as_nanos();
+        self.wait_nanos.fetch_add(nanos as u64, Ordering::Relaxed);
+        ret
+    }
+
+    #[cfg(not(feature = "sgx-lock-stats"))]
+    #[inline]
+    pub(crate) fn contended<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        f()
+    }
+
+    #[cfg(feature = "sgx-lock-stats")]
+    fn get(&self) -> LockStats {
+        let wait_nanos = self.wait_nanos.load(Ordering::Relaxed);
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended_acquisitions: self.contended_acquisitions.load(Ordering::Relaxed),
+            wait_usercalls: self.wait_usercalls.load(Ordering::Relaxed),
+            wait_time: Duration::new(wait_nanos / 1_000_000_000,
+                                     (wait_nanos % 1_000_000_000) as _),
+        }
+    }
+
+    #[cfg(not(feature = "sgx-lock-stats"))]
+    fn get(&self) -> LockStats {
+        LockStats::default()
+    }
+
+    fn reset(&self) {
+        #[cfg(feature = "sgx-lock-stats")]
+        {
+            self.acquisitions.store(0, Ordering::Relaxed);
+            self.contended_acquisitions.store(0, Ordering::Relaxed);
+            self.wait_usercalls.store(0, Ordering::Relaxed);
+            self.wait_nanos.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+pub(crate) static MUTEX: Counters = Counters::new();
+pub(crate) static REENTRANT_MUTEX: Counters = Counters::new();
+pub(crate) static RWLOCK: Counters = Counters::new();
+pub(crate) static CONDVAR: Counters = Counters::new();
+pub(crate) static BARRIER: Counters = Counters::new();
+pub(crate) static FUTEX: Counters = Counters::new();
+
+/// Returns the statistics of [`Mutex`](../../../sync/struct.Mutex.html).
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn mutex() -> LockStats {
+    MUTEX.get()
+}
+
+/// Returns the statistics of the reentrant mutex used by the standard library
+/// internally, for example to lock standard output.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn reentrant_mutex() -> LockStats {
+    REENTRANT_MUTEX.get()
+}
+
+/// Returns the statistics of [`RwLock`](../../../sync/struct.RwLock.html),
+/// including the locks used by the unwinder.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn rwlock() -> LockStats {
+    RWLOCK.get()
+}
+
+/// Returns the statistics of [`Condvar`](../../../sync/struct.Condvar.html).
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn condvar() -> LockStats {
+    CONDVAR.get()
+}
+
+/// Returns the statistics of [`Barrier`](../../../sync/struct.Barrier.html).
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn barrier() -> LockStats {
+    BARRIER.get()
+}
+
+/// Returns the statistics of [`futex::wait_on`](../futex/fn.wait_on.html).
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn futex() -> LockStats {
+    FUTEX.get()
+}
+
+/// Resets all statistics to zero, including the usercall statistics of the
+/// current thread.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn reset() {
+    MUTEX.reset();
+    REENTRANT_MUTEX.reset();
+    RWLOCK.reset();
+    CONDVAR.reset();
+    BARRIER.reset();
+    FUTEX.reset();
+    #[cfg(feature = "sgx-usercall-stats")]
+    {
+        let _ = USERCALLS.try_with(|usercalls| {
+            *usercalls.borrow_mut() = [(0, 0); USERCALL_NRS];
+        });
+    }
+}
+
+/// Statistics of one type of usercall made by a thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct UsercallStats {
+    /// The usercall number, see
+    /// [`UsercallNrs`](../usercalls/raw/enum.UsercallNrs.html).
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub nr: u64,
+    /// How many of these usercalls were made.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub count: u64,
+    /// The total number of time-stamp counter ticks spent in these usercalls,
+    /// or `0` without the `sgx-rdtsc` feature.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub ticks: u64,
+}
+
+// Usercall numbers from this one on aren't counted
+#[cfg(feature = "sgx-usercall-stats")]
+const USERCALL_NRS: usize = 32;
+
+// The count and ticks of the usercalls made by this thread, by number
+#[cfg(feature = "sgx-usercall-stats")]
+thread_local! {
+    static USERCALLS: RefCell<[(u64, u64); USERCALL_NRS]> =
+        RefCell::new([(0, 0); USERCALL_NRS]);
+}
+
+/// Returns the statistics of the usercalls made by the current thread, for
+/// every type of usercall it made. Statistics are only collected if the
+/// standard library is built with the `sgx-usercall-stats` feature.
+///
+/// Usercalls that don't return, usercalls made before the thread can use
+/// thread-local storage and usercalls submitted through the asynchronous
+/// usercall queue aren't counted.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn usercall_stats() -> Vec<UsercallStats> {
+    #[cfg(feature = "sgx-usercall-stats")]
+    {
+        if let Ok(stats) = USERCALLS.try_with(|usercalls| {
+            usercalls.borrow().iter().enumerate()
+                .filter(|&(_, &(count, _))| count > 0)
+                .map(|(nr, &(count, ticks))| UsercallStats { nr: nr as u64, count, ticks })
+                .collect()
+        }) {
+            return stats;
+        }
+    }
+    Vec::new()
+}
+
+/// Returns the start time of a usercall to pass to `usercall_done`.
+#[inline(always)]
+pub(crate) fn usercall_start() -> u64 {
+    #[cfg(feature = "sgx-usercall-stats")]
+    {
+        rdtsc().unwrap_or(0)
+    }
+    #[cfg(not(feature = "sgx-usercall-stats"))]
+    {
+        0
+    }
+}
+
+/// Records a usercall `nr` that started at `start`.
+#[inline]
+pub(crate) fn usercall_done(_nr: u64, _start: u64) {
+    #[cfg(feature = "sgx-usercall-stats")]
+    {
+        if !Tls::is_active() || _nr as usize >= USERCALL_NRS {
+            return;
+        }
+        let ticks = rdtsc().map_or(0, |now| now.wrapping_sub(_start));
+        let _ = USERCALLS.try_with(|usercalls| {
+            if let Ok(mut usercalls) = usercalls.try_borrow_mut() {
+                let entry = &mut usercalls[_nr as usize];
+                entry.0 += 1;
+                entry.1 += ticks;
+            }
+        });
+    }
+}
+
+/// A sample taken by the sampling profiler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub struct Sample {
+    /// The value of the time-stamp counter, or without the `sgx-rdtsc`
+    /// feature, the number of sampling points passed before this sample.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub timestamp: u64,
+    /// The return address of the sampled function, relative to the image
+    /// base.
+    #[unstable(feature = "sgx_platform", issue = "56975")]
+    pub address: u64,
+}
+
+// The most recent samples, oldest first once `next` wraps around
+struct Samples {
+    buf: Vec<Sample>,
+    capacity: usize,
+    next: usize,
+}
+
+impl Samples {
+    fn push(&mut self, sample: Sample) {
+        if self.buf.len() < self.capacity {
+            self.buf.push(sample);
+        } else if self.capacity > 0 {
+            self.buf[self.next] = sample;
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    fn take(&mut self) -> Vec<Sample> {
+        let mut buf = Vec::with_capacity(self.capacity);
+        buf.extend_from_slice(&self.buf[self.next..]);
+        buf.extend_from_slice(&self.buf[..self.next]);
+        self.buf.clear();
+        self.next = 0;
+        buf
+    }
+}
+
+static SAMPLING: AtomicBool = AtomicBool::new(false);
+static SAMPLE_PERIOD: AtomicU64 = AtomicU64::new(0);
+static LAST_SAMPLE: AtomicU64 = AtomicU64::new(0);
+// Sampling points passed while sampling, the clock without `sgx-rdtsc`
+static SAMPLE_POINTS: AtomicU64 = AtomicU64::new(0);
+static SAMPLES: SpinMutex<Option<Samples>> = SpinMutex::new(None);
+
+/// Starts sampling, taking at most one sample every `period` time-stamp
+/// counter ticks. The most recent `capacity` samples are kept, replacing any
+/// samples that haven't been taken yet.
+///
+/// Without the `sgx-rdtsc` feature, `period` is counted in sampling points,
+/// that is usercalls and allocations, instead.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn start_sampling(period: u64, capacity: usize) {
+    let samples = Samples { buf: Vec::with_capacity(capacity), capacity, next: 0 };
+    *SAMPLES.lock() = Some(samples);
+    SAMPLE_PERIOD.store(period, Ordering::Relaxed);
+    LAST_SAMPLE.store(0, Ordering::Relaxed);
+    SAMPLE_POINTS.store(0, Ordering::Relaxed);
+    SAMPLING.store(true, Ordering::SeqCst);
+}
+
+/// Stops sampling. Samples that were taken can still be retrieved with
+/// [`take_samples`](fn.take_samples.html).
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn stop_sampling() {
+    SAMPLING.store(false, Ordering::SeqCst);
+}
+
+/// Returns the samples taken so far, oldest first, and removes them.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub fn take_samples() -> Vec<Sample> {
+    match *SAMPLES.lock() {
+        Some(ref mut samples) => samples.take(),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the return address of the function that this is inlined into.
+#[inline(always)]
+pub(crate) fn return_address() -> u64 {
+    // Frame pointers are always kept on this target, the return address is
+    // stored just above the saved frame pointer.
+    unsafe {
+        let fp: *const u64;
+        asm!("mov %rbp, $0" : "=r"(fp));
+        *fp.add(1)
+    }
+}
+
+/// Samples the function that this is inlined into, if sampling is enabled.
+#[inline(always)]
+pub(crate) fn sample() {
+    if SAMPLING.load(Ordering::Relaxed) {
+        take_sample(return_address())
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn take_sample(address: u64) {
+    let now = rdtsc().unwrap_or_else(|| SAMPLE_POINTS.fetch_add(1, Ordering::Relaxed));
+    let last = LAST_SAMPLE.load(Ordering::Relaxed);
+    if now.wrapping_sub(last) < SAMPLE_PERIOD.load(Ordering::Relaxed) ||
+        LAST_SAMPLE.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+        return;
+    }
+    // Don't wait for a thread that is taking the samples, and don't deadlock
+    // if this is an allocation made while holding the lock.
+    if let Some(mut samples) = SAMPLES.try_lock() {
+        if let Some(ref mut samples) = *samples {
+            samples.push(Sample { timestamp: now, address: address.wrapping_sub(image_base()) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let sample = |timestamp| Sample { timestamp, address: 0 };
+        let mut samples = Samples { buf: Vec::new(), capacity: 2, next: 0 };
+        samples.push(sample(1));
+        assert_eq!(samples.take(), [sample(1)]);
+        samples.push(sample(2));
+        samples.push(sample(3));
+        samples.push(sample(4));
+        assert_eq!(samples.take(), [sample(3), sample(4)]);
+        assert!(samples.take().is_empty());
+    }
+}
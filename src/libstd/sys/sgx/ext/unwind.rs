@@ -0,0 +1,57 @@
+//! Registering unwind information of code loaded at runtime.
+//!
+//! The unwinder only knows about the code in the enclave image. Enclaves that
+//! generate or load code at runtime must register the DWARF unwind
+//! information of that code, so that panics can unwind through it and
+//! backtraces can include its frames.
+#![unstable(feature = "sgx_platform", issue = "56975")]
+
+use crate::marker::PhantomData;
+
+use super::super::abi::mem::is_enclave_range;
+
+extern "C" {
+    // Provided by libunwind
+    fn __register_frame(fde: *const u8);
+    fn __deregister_frame(fde: *const u8);
+}
+
+/// A registered frame description entry. The entry is deregistered when this
+/// value is dropped.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+#[derive(Debug)]
+pub struct Registration<'a> {
+    fde: *const u8,
+    _fde: PhantomData<&'a [u8]>,
+}
+
+/// Registers a DWARF frame description entry (FDE) in `.eh_frame` format with
+/// the unwinder.
+///
+/// `fde` starts with the FDE's length field and must not be used for anything
+/// else while it is registered, the unwinder parses it lazily.
+///
+/// # Panics
+///
+/// Panics if `fde` is not in enclave memory, because the unwinder must not
+/// trust unwind information provided by userspace.
+///
+/// # Safety
+///
+/// `fde` must be a well-formed FDE, whose CIE pointer points to a well-formed
+/// common information entry that lives at least as long, and it must
+/// correctly describe the code it covers, otherwise unwinding causes
+/// undefined behavior.
+#[unstable(feature = "sgx_platform", issue = "56975")]
+pub unsafe fn register_fde(fde: &[u8]) -> Registration<'_> {
+    assert!(is_enclave_range(fde.as_ptr(), fde.len()), "FDE must be in enclave memory");
+    __register_frame(fde.as_ptr());
+    Registration { fde: fde.as_ptr(), _fde: PhantomData }
+}
+
+#[unstable(feature = "sgx_platform", issue = "56975")]
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        unsafe { __deregister_frame(self.fde) }
+    }
+}
@@ -1,6 +1,6 @@
 use fortanix_sgx_abi::Fd;
 
-use crate::io;
+use crate::io::{self, IoVec, IoVecMut};
 use crate::mem;
 use crate::sys::{AsInner, FromInner, IntoInner};
 use super::abi::usercalls;
@@ -24,14 +24,28 @@ impl FileDesc {
         fd
     }
 
+    // FIXME: Every read and write exits the enclave. Bulk transfers could
+    // instead move bytes through a ring buffer in user memory, like the
+    // usercall queues in `usercalls::async_queues`, and only exit when the
+    // ring is empty or full. Userspace has to agree to serve a stream that
+    // way, which needs a usercall to set up the ring for a file descriptor
+    // and events to signal it, and the usercall ABI has neither.
     pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
         usercalls::read(self.fd, buf)
     }
 
+    pub fn read_vectored(&self, bufs: &mut [IoVecMut<'_>]) -> io::Result<usize> {
+        usercalls::read_vectored(self.fd, bufs)
+    }
+
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
         usercalls::write(self.fd, buf)
     }
 
+    pub fn write_vectored(&self, bufs: &[IoVec<'_>]) -> io::Result<usize> {
+        usercalls::write_vectored(self.fd, bufs)
+    }
+
     pub fn flush(&self) -> io::Result<()> {
         usercalls::flush(self.fd)
     }
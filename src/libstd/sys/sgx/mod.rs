@@ -8,10 +8,13 @@ use crate::os::raw::c_char;
 use crate::sync::atomic::{AtomicBool, Ordering};
 
 pub mod abi;
+pub mod abort;
+mod deadlock;
 mod waitqueue;
 
 pub mod alloc;
 pub mod args;
+pub mod barrier;
 #[cfg(feature = "backtrace")]
 pub mod backtrace;
 pub mod cmath;
@@ -32,6 +35,7 @@ pub mod rwlock;
 pub mod stack_overflow;
 pub mod thread;
 pub mod thread_local;
+pub mod thread_parking;
 pub mod time;
 pub mod stdio;
 
@@ -127,7 +131,7 @@ pub unsafe fn strlen(mut s: *const c_char) -> usize {
 }
 
 pub unsafe fn abort_internal() -> ! {
-    abi::usercalls::exit(true)
+    abort::abort_with_code(abort::AbortReason::Abort)
 }
 
 pub fn hashmap_random_keys() -> (u64, u64) {
@@ -1,17 +1,31 @@
-use fortanix_sgx_abi::Tcs;
+use crate::cell::UnsafeCell;
+use crate::sync::atomic::{AtomicUsize, Ordering};
 
 use super::abi::thread;
 
-use super::waitqueue::{WaitVariable, WaitQueue, SpinMutex, NotifiedTcs, try_lock_or_false};
+use super::deadlock;
+use super::ext::perf::{self, Counters};
+use super::waitqueue::{WaitVariable, WaitQueue, SpinMutex};
+
+const UNLOCKED: usize = 0;
+const LOCKED: usize = 1;
+const CONTENDED: usize = 2;
 
 pub struct Mutex {
-    inner: SpinMutex<WaitVariable<bool>>,
+    // One of `UNLOCKED`, `LOCKED` or `CONTENDED`. Uncontended lock and unlock
+    // only touch this field. `CONTENDED` means there might be threads in the
+    // wait queue, in which case the lock is passed to them directly.
+    state: AtomicUsize,
+    waiters: SpinMutex<WaitVariable<()>>,
 }
 
 // Implementation according to “Operating Systems: Three Easy Pieces”, chapter 28
 impl Mutex {
     pub const fn new() -> Mutex {
-        Mutex { inner: SpinMutex::new(WaitVariable::new(false)) }
+        Mutex {
+            state: AtomicUsize::new(UNLOCKED),
+            waiters: SpinMutex::new(WaitVariable::new(())),
+        }
     }
 
     #[inline]
@@ -19,58 +33,110 @@ impl Mutex {
 
     #[inline]
     pub unsafe fn lock(&self) {
-        let mut guard = self.inner.lock();
-        if *guard.lock_var() {
+        self.lock_counted(&perf::MUTEX)
+    }
+
+    // `lock`, recording the acquisition in `stats`
+    #[inline]
+    unsafe fn lock_counted(&self, stats: &Counters) {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended(stats)
+        }
+        deadlock::acquired(self);
+        stats.acquired();
+    }
+
+    #[cold]
+    unsafe fn lock_contended(&self, stats: &Counters) {
+        let guard = self.waiters.lock();
+        if self.state.swap(CONTENDED, Ordering::Acquire) == UNLOCKED {
+            // The lock was released in the meantime, we are just now
+            // obtaining it
+        } else {
             // Another thread has the lock, wait
-            WaitQueue::wait(guard)
+            deadlock::blocking(self);
+            WaitQueue::wait(guard, stats);
+            deadlock::unblocked();
             // Another thread has passed the lock to us
-        } else {
-            // We are just now obtaining the lock
-            *guard.lock_var_mut() = true;
         }
     }
 
     #[inline]
     pub unsafe fn unlock(&self) {
-        let guard = self.inner.lock();
-        if let Err(mut guard) = WaitQueue::notify_one(guard) {
-            // No other waiters, unlock
-            *guard.lock_var_mut() = false;
-        } else {
-            // There was a thread waiting, just pass the lock
+        deadlock::released(self);
+        if self.state.compare_exchange(LOCKED, UNLOCKED, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            self.unlock_contended()
+        }
+    }
+
+    #[cold]
+    unsafe fn unlock_contended(&self) {
+        let guard = self.waiters.lock();
+        match WaitQueue::notify_one(guard) {
+            Err(_guard) => {
+                // No other waiters, unlock
+                self.state.store(UNLOCKED, Ordering::Release);
+            },
+            Ok(guard) => {
+                // There was a thread waiting, just pass the lock. The state
+                // can only change while holding `waiters`, so if no one else
+                // is waiting, the next unlock may take the fast path again.
+                if guard.queue_empty() {
+                    self.state.store(LOCKED, Ordering::Relaxed);
+                }
+            }
         }
     }
 
     #[inline]
     pub unsafe fn try_lock(&self) -> bool {
-        let mut guard = try_lock_or_false!(self.inner);
-        if *guard.lock_var() {
-            // Another thread has the lock
-            false
-        } else {
-            // We are just now obtaining the lock
-            *guard.lock_var_mut() = true;
-            true
+        self.try_lock_counted(&perf::MUTEX)
+    }
+
+    // `try_lock`, recording the acquisition in `stats`
+    #[inline]
+    unsafe fn try_lock_counted(&self, stats: &Counters) -> bool {
+        let locked = self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked {
+            deadlock::acquired(self);
+            stats.acquired();
         }
+        locked
     }
 
     #[inline]
     pub unsafe fn destroy(&self) {}
 }
 
-struct ReentrantLock {
-    owner: Option<Tcs>,
-    count: usize
-}
-
 pub struct ReentrantMutex {
-    inner: SpinMutex<WaitVariable<ReentrantLock>>,
+    inner: Mutex,
+    // The TCS address of the thread holding `inner`, or 0. Only the owning
+    // thread ever stores its own address here, so it can use a relaxed load
+    // to check whether it already holds the lock.
+    owner: AtomicUsize,
+    // Only accessed by the owning thread
+    count: UnsafeCell<usize>,
 }
 
+unsafe impl Send for ReentrantMutex {}
+unsafe impl Sync for ReentrantMutex {}
+
+// A reentrant lock by a thread that already holds the mutex only touches
+// `owner` and `count`. Otherwise, this relies on the fast path of `Mutex`, so
+// the enclave is only exited if another thread actually needs to be blocked or
+// woken up.
 impl ReentrantMutex {
     pub const fn uninitialized() -> ReentrantMutex {
         ReentrantMutex {
-            inner: SpinMutex::new(WaitVariable::new(ReentrantLock { owner: None, count: 0 }))
+            inner: Mutex::new(),
+            owner: AtomicUsize::new(0),
+            count: UnsafeCell::new(0),
         }
     }
 
@@ -79,62 +145,112 @@ impl ReentrantMutex {
 
     #[inline]
     pub unsafe fn lock(&self) {
-        let mut guard = self.inner.lock();
-        match guard.lock_var().owner {
-            Some(tcs) if tcs != thread::current() => {
-                // Another thread has the lock, wait
-                WaitQueue::wait(guard);
-                // Another thread has passed the lock to us
-            },
-            _ => {
-                // We are just now obtaining the lock
-                guard.lock_var_mut().owner = Some(thread::current());
-                guard.lock_var_mut().count += 1;
-            },
+        let tcs = thread::current().as_ptr() as usize;
+        if self.owner.load(Ordering::Relaxed) == tcs {
+            self.increment_count();
+        } else {
+            self.inner.lock_counted(&perf::REENTRANT_MUTEX);
+            self.owner.store(tcs, Ordering::Relaxed);
+            *self.count.get() = 1;
         }
     }
 
+    #[inline]
+    unsafe fn increment_count(&self) {
+        *self.count.get() = rtunwrap!(Some, (*self.count.get()).checked_add(1));
+        perf::REENTRANT_MUTEX.acquired();
+    }
+
     #[inline]
     pub unsafe fn unlock(&self) {
-        let mut guard = self.inner.lock();
-        if guard.lock_var().count > 1 {
-            guard.lock_var_mut().count -= 1;
-        } else {
-            match WaitQueue::notify_one(guard) {
-                Err(mut guard) => {
-                    // No other waiters, unlock
-                    guard.lock_var_mut().count = 0;
-                    guard.lock_var_mut().owner = None;
-                },
-                Ok(mut guard) => {
-                    // There was a thread waiting, just pass the lock
-                    if let NotifiedTcs::Single(tcs) = guard.notified_tcs() {
-                        guard.lock_var_mut().owner = Some(tcs)
-                    } else {
-                        unreachable!() // called notify_one
-                    }
-                }
-            }
+        *self.count.get() -= 1;
+        if *self.count.get() == 0 {
+            self.owner.store(0, Ordering::Relaxed);
+            self.inner.unlock();
         }
     }
 
     #[inline]
     pub unsafe fn try_lock(&self) -> bool {
-        let mut guard = try_lock_or_false!(self.inner);
-        match guard.lock_var().owner {
-            Some(tcs) if tcs != thread::current() => {
-                // Another thread has the lock
-                false
-            },
-            _ => {
-                // We are just now obtaining the lock
-                guard.lock_var_mut().owner = Some(thread::current());
-                guard.lock_var_mut().count += 1;
-                true
-            },
+        let tcs = thread::current().as_ptr() as usize;
+        if self.owner.load(Ordering::Relaxed) == tcs {
+            self.increment_count();
+            true
+        } else if self.inner.try_lock_counted(&perf::REENTRANT_MUTEX) {
+            self.owner.store(tcs, Ordering::Relaxed);
+            *self.count.get() = 1;
+            true
+        } else {
+            false
         }
     }
 
     #[inline]
     pub unsafe fn destroy(&self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use crate::sync::atomic::AtomicBool;
+    use crate::thread;
+
+    #[test]
+    fn reentrant() {
+        let m = Arc::new(ReentrantMutex::uninitialized());
+        unsafe {
+            m.lock();
+            m.lock();
+            assert!(m.try_lock());
+        }
+        // Another thread can't take the lock while it is held
+        let m2 = m.clone();
+        assert!(!thread::spawn(move || unsafe { m2.try_lock() }).join().unwrap());
+        unsafe {
+            m.unlock();
+            m.unlock();
+            m.unlock();
+        }
+        let m2 = m.clone();
+        assert!(thread::spawn(move || unsafe {
+            let locked = m2.try_lock();
+            if locked {
+                m2.unlock();
+            }
+            locked
+        }).join().unwrap());
+    }
+
+    #[bench]
+    fn bench_uncontended(b: &mut test::Bencher) {
+        let m = Mutex::new();
+        b.iter(|| unsafe {
+            m.lock();
+            m.unlock();
+        });
+    }
+
+    #[bench]
+    fn bench_contended(b: &mut test::Bencher) {
+        let m = Arc::new(Mutex::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let t = {
+            let (m, done) = (m.clone(), done.clone());
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    unsafe {
+                        m.lock();
+                        m.unlock();
+                    }
+                }
+            })
+        };
+        b.iter(|| unsafe {
+            m.lock();
+            m.unlock();
+        });
+        done.store(true, Ordering::Relaxed);
+        t.join().unwrap();
+    }
+}
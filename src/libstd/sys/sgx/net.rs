@@ -1,4 +1,6 @@
+use crate::cmp;
 use crate::fmt;
+use crate::mem;
 use crate::io::{self, IoVec, IoVecMut};
 use crate::net::{SocketAddr, Shutdown, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
 use crate::time::Duration;
@@ -6,21 +8,113 @@ use crate::sys::{unsupported, Void, sgx_ineffective, AsInner, FromInner, IntoInn
 use crate::sys::fd::FileDesc;
 use crate::convert::TryFrom;
 use crate::error;
-use crate::sync::Arc;
+use crate::sync::{mpsc, Arc, Mutex};
+use crate::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::thread;
 
 use super::abi::usercalls;
 
 const DEFAULT_FAKE_TTL: u32 = 64;
 
+// How many threads `TcpStream::connect_timeout` may keep connecting at once
+const MAX_CONNECT_HELPERS: usize = 4;
+static CONNECT_HELPERS: AtomicUsize = AtomicUsize::new(0);
+
+// How many threads reads with a timeout may keep reading at once
+const MAX_READ_HELPERS: usize = 4;
+static READ_HELPERS: AtomicUsize = AtomicUsize::new(0);
+
+// Counts a helper thread in `helpers`, unless there already are `max`.
+fn reserve_helper(helpers: &AtomicUsize, max: usize, msg: &'static str) -> io::Result<()> {
+    if helpers.fetch_add(1, Ordering::SeqCst) >= max {
+        helpers.fetch_sub(1, Ordering::SeqCst);
+        return Err(io::Error::new(io::ErrorKind::Other, msg));
+    }
+    Ok(())
+}
+
+// Clones of a socket share the file descriptor, which is closed once the last
+// clone is dropped, so cloning doesn't need a usercall.
 #[derive(Debug, Clone)]
 pub struct Socket {
     inner: Arc<FileDesc>,
     local_addr: Option<String>,
+    read_state: Arc<ReadState>,
+    // The directions shut down with `TcpStream::shutdown`
+    shut_down: Arc<AtomicUsize>,
+}
+
+const SHUT_RD: usize = 1;
+const SHUT_WR: usize = 2;
+
+// State of the reading direction of a socket, shared with its clones. Writing
+// has no state, so writers never wait for readers.
+#[derive(Debug, Default)]
+struct ReadState {
+    // Data that was read from the socket by `TcpStream::peek`, but not yet
+    // by `TcpStream::read`.
+    peeked: Mutex<Vec<u8>>,
+    // Set while `peeked` may be non-empty, so that reads only take the lock
+    // after a peek.
+    has_peeked: AtomicBool,
+    // Locked after `peeked` if both are needed.
+    timeout: Mutex<ReadTimeout>,
+    // Set while a read timeout is set, the socket is non-blocking or a read
+    // is pending, so that reads only take the locks then.
+    timed: AtomicBool,
+}
+
+// The result of a read on a helper thread
+type PendingRead = mpsc::Receiver<io::Result<Vec<u8>>>;
+
+#[derive(Debug, Default)]
+struct ReadTimeout {
+    dur: Option<Duration>,
+    nonblocking: bool,
+    // A read that timed out but is still in progress. The next read waits
+    // for it, so no data is lost or reordered.
+    pending: Option<PendingRead>,
+}
+
+impl ReadState {
+    fn set_timed(&self, timeout: &ReadTimeout) {
+        let timed = timeout.dur.is_some() || timeout.nonblocking || timeout.pending.is_some();
+        self.timed.store(timed, Ordering::Release);
+    }
 }
 
 impl Socket {
     fn new(fd: usercalls::raw::Fd, local_addr: String) -> Socket {
-        Socket { inner: Arc::new(FileDesc::new(fd)), local_addr: Some(local_addr) }
+        Socket::from_fd(FileDesc::new(fd), Some(local_addr))
+    }
+
+    fn from_fd(fd: FileDesc, local_addr: Option<String>) -> Socket {
+        Socket {
+            inner: Arc::new(fd),
+            local_addr,
+            read_state: Arc::new(ReadState::default()),
+            shut_down: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn has_peeked(&self) -> bool {
+        self.read_state.has_peeked.load(Ordering::Acquire)
+    }
+
+    fn is_timed(&self) -> bool {
+        self.read_state.timed.load(Ordering::Acquire)
+    }
+
+    fn is_shut_down(&self, how: usize) -> bool {
+        self.shut_down.load(Ordering::Acquire) & how != 0
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.is_shut_down(SHUT_WR) {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe,
+                                      "the stream was shut down for writing"));
+        }
+        Ok(())
     }
 }
 
@@ -28,16 +122,20 @@ impl AsInner<FileDesc> for Socket {
     fn as_inner(&self) -> &FileDesc { &self.inner }
 }
 
+// Fails if the socket is shared, or if peeked data would be lost.
 impl TryIntoInner<FileDesc> for Socket {
     fn try_into_inner(self) -> Result<FileDesc, Socket> {
-        let Socket { inner, local_addr } = self;
-        Arc::try_unwrap(inner).map_err(|inner| Socket { inner, local_addr } )
+        if self.has_peeked() {
+            return Err(self);
+        }
+        let Socket { inner, local_addr, read_state, shut_down } = self;
+        Arc::try_unwrap(inner).map_err(|inner| Socket { inner, local_addr, read_state, shut_down })
     }
 }
 
 impl FromInner<FileDesc> for Socket {
     fn from_inner(inner: FileDesc) -> Socket {
-        Socket { inner: Arc::new(inner), local_addr: None }
+        Socket::from_fd(inner, None)
     }
 }
 
@@ -77,6 +175,11 @@ fn io_err_to_addr(result: io::Result<&SocketAddr>) -> io::Result<String> {
     }
 }
 
+// Addresses that aren't IP socket addresses fail to convert with a
+// `NonIpSockAddr` error carrying the address, see `ext::net`.
+// FIXME: Without structured addresses from userspace, an address that looks
+// like an IP socket address can't be told apart from a name of another
+// transport that happens to look like one.
 fn addr_to_sockaddr(addr: &Option<String>) -> io::Result<SocketAddr> {
     addr.as_ref()
         .ok_or(io::ErrorKind::AddrNotAvailable)?
@@ -86,9 +189,23 @@ fn addr_to_sockaddr(addr: &Option<String>) -> io::Result<SocketAddr> {
 }
 
 impl TcpStream {
+    // Addresses that are already resolved are tried in sequence by
+    // `net::each_addr`. A host name is passed to userspace in one piece, and
+    // userspace picks the address to connect to.
+    // FIXME: Trying each address a name resolves to, possibly staggered once
+    // connecting doesn't block, needs the resolve usercall noted at
+    // `LookupHost`.
     pub fn connect(addr: io::Result<&SocketAddr>) -> io::Result<TcpStream> {
-        let addr = io_err_to_addr(addr)?;
-        let (fd, local_addr, peer_addr) = usercalls::connect_stream(&addr)?;
+        TcpStream::connect_str(&io_err_to_addr(addr)?)
+    }
+
+    // FIXME: `connect_stream` only takes the destination, so the local
+    // address and port of an outgoing stream are chosen by userspace. Binding
+    // them, e.g. for policy routing on a multi-homed host, needs an optional
+    // local address argument to the usercall. A builder for such streams
+    // would go in `ext::net`.
+    pub fn connect_str(addr: &str) -> io::Result<TcpStream> {
+        let (fd, local_addr, peer_addr) = usercalls::connect_stream(addr)?;
         Ok(TcpStream { inner: Socket::new(fd, local_addr), peer_addr: Some(peer_addr) })
     }
 
@@ -97,73 +214,282 @@ impl TcpStream {
             return Err(io::Error::new(io::ErrorKind::InvalidInput,
                                       "cannot set a 0 duration timeout"));
         }
-        Self::connect(Ok(addr)) // FIXME: ignoring timeout
+        // The `connect_stream` usercall can't time out, so connect on another
+        // thread and stop waiting for it after `dur`. A connection that is
+        // only established after that is closed.
+        //
+        // The thread occupies a TCS until `connect_stream` returns, which
+        // may be never if the runner doesn't time out connecting. TCSs are a
+        // fixed budget set when the enclave is signed, so the number of
+        // these threads is capped, and a connection attempt fails if too
+        // many earlier ones are still in progress, rather than leaving no
+        // TCS for `thread::spawn`.
+        reserve_helper(&CONNECT_HELPERS, MAX_CONNECT_HELPERS,
+                       "too many timed out connection attempts in progress")?;
+        let (tx, rx) = mpsc::channel();
+        let addr = *addr;
+        let spawned = thread::Builder::new().spawn(move || {
+            let result = TcpStream::connect(Ok(&addr));
+            CONNECT_HELPERS.fetch_sub(1, Ordering::SeqCst);
+            let _ = tx.send(result);
+        });
+        if let Err(e) = spawned {
+            CONNECT_HELPERS.fetch_sub(1, Ordering::SeqCst);
+            return Err(e);
+        }
+        match rx.recv_timeout(dur) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::Other, "connecting thread panicked"))
+            }
+        }
     }
 
+    // Only the `wait` usercall takes a timeout, so a read with a timeout is
+    // done on another thread, like `connect_timeout`, and given up on after
+    // the timeout. It isn't cancelled though: the next read, peek or copy
+    // waits for it and gets its data, so nothing is lost. A socket can't be
+    // converted to a raw file descriptor while such a read is pending.
+    // Non-blocking reads work the same way, with a timeout of zero.
+    //
+    // The same doesn't work for writes: when a write has been given up on,
+    // the data may still be sent, so there's no way to report how much was
+    // written. Setting a write timeout fails instead of being ignored.
+    // FIXME: Write timeouts need `write` to take a deadline, with userspace
+    // returning `TimedOut` once it has passed, which requires an ABI change.
     pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        match dur {
-            Some(dur) if dur == Duration::default() => {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                          "cannot set a 0 duration timeout"));
-            }
-            _ => sgx_ineffective(())
+        if dur == Some(Duration::default()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "cannot set a 0 duration timeout"));
         }
+        let mut timeout = self.inner.read_state.timeout.lock().unwrap();
+        timeout.dur = dur;
+        self.inner.read_state.set_timed(&timeout);
+        Ok(())
     }
 
     pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
         match dur {
             Some(dur) if dur == Duration::default() => {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput,
-                                          "cannot set a 0 duration timeout"));
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   "cannot set a 0 duration timeout"))
             }
-            _ => sgx_ineffective(())
+            Some(_) => Err(io::Error::new(io::ErrorKind::Other,
+                                          "write timeouts are not supported on SGX")),
+            None => Ok(()),
         }
     }
 
     pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
-        sgx_ineffective(None)
+        Ok(self.inner.read_state.timeout.lock().unwrap().dur)
     }
 
     pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
-        sgx_ineffective(None)
+        Ok(None)
     }
 
-    pub fn peek(&self, _: &mut [u8]) -> io::Result<usize> {
-        Ok(0)
+    // Starts reading up to `len` bytes on another thread.
+    fn spawn_read(&self, len: usize) -> io::Result<PendingRead> {
+        reserve_helper(&READ_HELPERS, MAX_READ_HELPERS,
+                       "too many timed out reads in progress")?;
+        let (tx, rx) = mpsc::channel();
+        let fd = self.inner.inner.clone();
+        let spawned = thread::Builder::new().spawn(move || {
+            let mut data = vec![0; len];
+            let result = fd.read(&mut data).map(|len| {
+                data.truncate(len);
+                data
+            });
+            READ_HELPERS.fetch_sub(1, Ordering::SeqCst);
+            let _ = tx.send(result);
+        });
+        if let Err(e) = spawned {
+            READ_HELPERS.fetch_sub(1, Ordering::SeqCst);
+            return Err(e);
+        }
+        Ok(rx)
+    }
+
+    // Reads up to `len` bytes into the empty `peeked`, waiting for a pending
+    // read first, and honoring the read timeout and non-blocking mode.
+    fn fill_peeked(&self, peeked: &mut Vec<u8>, len: usize) -> io::Result<()> {
+        let state = &self.inner.read_state;
+        let mut timeout = state.timeout.lock().unwrap();
+        let dur = timeout.dur;
+        let nonblocking = timeout.nonblocking;
+        let pending = match (timeout.pending.take(), dur) {
+            (Some(pending), _) => pending,
+            (None, Some(_)) => self.spawn_read(len)?,
+            (None, None) if nonblocking => self.spawn_read(len)?,
+            (None, None) => {
+                drop(timeout);
+                peeked.resize(len, 0);
+                match self.inner.inner.read(peeked) {
+                    Ok(len) => peeked.truncate(len),
+                    Err(e) => {
+                        peeked.clear();
+                        return Err(e);
+                    }
+                }
+                state.has_peeked.store(!peeked.is_empty(), Ordering::Release);
+                return Ok(());
+            }
+        };
+        // Other readers wait for `peeked`, but the timeout can be changed
+        // meanwhile.
+        drop(timeout);
+        let result = match dur {
+            _ if nonblocking => pending.try_recv().map_err(|e| match e {
+                mpsc::TryRecvError::Empty => mpsc::RecvTimeoutError::Timeout,
+                mpsc::TryRecvError::Disconnected => mpsc::RecvTimeoutError::Disconnected,
+            }),
+            Some(dur) => pending.recv_timeout(dur),
+            None => pending.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        let mut timeout = state.timeout.lock().unwrap();
+        let result = match result {
+            Ok(Ok(data)) => {
+                *peeked = data;
+                state.has_peeked.store(!peeked.is_empty(), Ordering::Release);
+                Ok(())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                timeout.pending = Some(pending);
+                let msg = if nonblocking { "read would block" } else { "read timed out" };
+                Err(io::Error::new(io::ErrorKind::WouldBlock, msg))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::Other, "reading thread panicked"))
+            }
+        };
+        state.set_timed(&timeout);
+        result
+    }
+
+    // There is no way to ask userspace to peek, so the data is read into the
+    // enclave and kept for the next `read`.
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inner.is_shut_down(SHUT_RD) {
+            return Ok(0);
+        }
+        let mut peeked = self.inner.read_state.peeked.lock().unwrap();
+        if peeked.is_empty() {
+            self.fill_peeked(&mut peeked, buf.len())?;
+        }
+        let len = cmp::min(buf.len(), peeked.len());
+        buf[..len].copy_from_slice(&peeked[..len]);
+        Ok(len)
     }
 
     pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inner.is_shut_down(SHUT_RD) {
+            return Ok(0);
+        }
+        if self.inner.has_peeked() || self.inner.is_timed() {
+            let mut peeked = self.inner.read_state.peeked.lock().unwrap();
+            if peeked.is_empty() {
+                self.fill_peeked(&mut peeked, buf.len())?;
+            }
+            let len = cmp::min(buf.len(), peeked.len());
+            buf[..len].copy_from_slice(&peeked[..len]);
+            peeked.drain(..len);
+            self.inner.read_state.has_peeked.store(!peeked.is_empty(), Ordering::Release);
+            return Ok(len);
+        }
         self.inner.inner.read(buf)
     }
 
+    // Takes the data that was peeked but not read yet, waiting for a pending
+    // read.
+    pub fn take_peeked(&self) -> Vec<u8> {
+        if !self.inner.has_peeked() && !self.inner.is_timed() {
+            return Vec::new();
+        }
+        let mut peeked = self.inner.read_state.peeked.lock().unwrap();
+        let mut timeout = self.inner.read_state.timeout.lock().unwrap();
+        if let Some(pending) = timeout.pending.take() {
+            // An error is reported by the reads after the copy instead
+            if let Ok(Ok(data)) = pending.recv() {
+                peeked.extend_from_slice(&data);
+            }
+            self.inner.read_state.set_timed(&timeout);
+        }
+        self.inner.read_state.has_peeked.store(false, Ordering::Release);
+        mem::replace(&mut *peeked, Vec::new())
+    }
+
     pub fn read_vectored(&self, bufs: &mut [IoVecMut<'_>]) -> io::Result<usize> {
-        io::default_read_vectored(|b| self.read(b), bufs)
+        if self.inner.is_shut_down(SHUT_RD) {
+            return Ok(0);
+        }
+        if self.inner.has_peeked() || self.inner.is_timed() {
+            return io::default_read_vectored(|b| self.read(b), bufs);
+        }
+        self.inner.inner.read_vectored(bufs)
     }
 
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.check_writable()?;
         self.inner.inner.write(buf)
     }
 
     pub fn write_vectored(&self, bufs: &[IoVec<'_>]) -> io::Result<usize> {
-        io::default_write_vectored(|b| self.write(b), bufs)
+        self.inner.check_writable()?;
+        self.inner.inner.write_vectored(bufs)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.inner.inner.flush()
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         addr_to_sockaddr(&self.peer_addr)
     }
 
+    pub fn peer_addr_str(&self) -> Option<&str> {
+        self.peer_addr.as_ref().map(|addr| &addr[..])
+    }
+
     pub fn socket_addr(&self) -> io::Result<SocketAddr> {
         addr_to_sockaddr(&self.inner.local_addr)
     }
 
-    pub fn shutdown(&self, _: Shutdown) -> io::Result<()> {
-        sgx_ineffective(())
+    pub fn local_addr_str(&self) -> Option<&str> {
+        self.inner.local_addr.as_ref().map(|addr| &addr[..])
+    }
+
+    // Shutting down a direction makes reads return end of file, or writes
+    // fail, on this stream and its clones.
+    // FIXME: The peer isn't told: the only way to end a stream is the `close`
+    // usercall, which closes both directions and can't be called while
+    // clones of the stream exist. Sending the end of the stream after
+    // shutting down writing needs a shutdown usercall with a direction
+    // argument, which the usercall ABI doesn't have.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let how = match how {
+            Shutdown::Read => SHUT_RD,
+            Shutdown::Write => SHUT_WR,
+            Shutdown::Both => SHUT_RD | SHUT_WR,
+        };
+        self.inner.shut_down.fetch_or(how, Ordering::AcqRel);
+        if how & SHUT_WR != 0 {
+            return sgx_ineffective(());
+        }
+        Ok(())
     }
 
     pub fn duplicate(&self) -> io::Result<TcpStream> {
         Ok(self.clone())
     }
 
+    // FIXME: Socket options are up to userspace, the enclave can't set or
+    // query them. `TCP_NODELAY`, the TTL and linger need a pair of usercalls
+    // to set and get an option of a stream, which the usercall ABI doesn't
+    // have. Until then, the defaults of the runner apply.
     pub fn set_nodelay(&self, _: bool) -> io::Result<()> {
         sgx_ineffective(())
     }
@@ -184,8 +510,18 @@ impl TcpStream {
         Ok(None)
     }
 
-    pub fn set_nonblocking(&self, _: bool) -> io::Result<()> {
-        sgx_ineffective(())
+    // Reads are made non-blocking like read timeouts, see `set_read_timeout`.
+    // A read that finds no data starts reading on another thread, so a later
+    // read returns the data once it has arrived.
+    // FIXME: `write` and `accept_stream` always block. A `WouldBlock` error
+    // returned by userspace is already mapped to `io::ErrorKind::WouldBlock`,
+    // but there is no way to ask userspace not to block, which needs a flag
+    // on those usercalls or a usercall to set the mode of a file descriptor.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let mut timeout = self.inner.read_state.timeout.lock().unwrap();
+        timeout.nonblocking = nonblocking;
+        self.inner.read_state.set_timed(&timeout);
+        Ok(())
     }
 }
 
@@ -227,8 +563,11 @@ impl fmt::Debug for TcpListener {
 
 impl TcpListener {
     pub fn bind(addr: io::Result<&SocketAddr>) -> io::Result<TcpListener> {
-        let addr = io_err_to_addr(addr)?;
-        let (fd, local_addr) = usercalls::bind_stream(&addr)?;
+        TcpListener::bind_str(&io_err_to_addr(addr)?)
+    }
+
+    pub fn bind_str(addr: &str) -> io::Result<TcpListener> {
+        let (fd, local_addr) = usercalls::bind_stream(addr)?;
         Ok(TcpListener { inner: Socket::new(fd, local_addr) })
     }
 
@@ -236,6 +575,10 @@ impl TcpListener {
         addr_to_sockaddr(&self.inner.local_addr)
     }
 
+    pub fn local_addr_str(&self) -> Option<&str> {
+        self.inner.local_addr.as_ref().map(|addr| &addr[..])
+    }
+
     pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         let (fd, local_addr, peer_addr) = usercalls::accept_stream(self.inner.inner.raw())?;
         let peer_addr = Some(peer_addr);
@@ -267,6 +610,7 @@ impl TcpListener {
         Ok(None)
     }
 
+    // FIXME: `accept_stream` always blocks, see `TcpStream::set_nonblocking`
     pub fn set_nonblocking(&self, _: bool) -> io::Result<()> {
         sgx_ineffective(())
     }
@@ -288,6 +632,10 @@ impl FromInner<Socket> for TcpListener {
     }
 }
 
+// FIXME: The usercall ABI only has stream sockets. UDP needs datagram
+// usercalls, at least `bind_datagram`, `send_to` and `recv_from` with the
+// peer address passed as a string like for streams. Until then, every
+// `UdpSocket` operation is unsupported.
 pub struct UdpSocket(Void);
 
 impl UdpSocket {
@@ -443,6 +791,12 @@ impl fmt::Display for NonIpSockAddr {
     }
 }
 
+// Host names can't be resolved inside the enclave. Instead, lookups fail with
+// a `NonIpSockAddr` error carrying the host, which `connect` and `bind` pass
+// to userspace as is.
+// FIXME: Resolving a name to a list of addresses, so that ports can be set
+// and addresses filtered like on other platforms, needs a resolve usercall
+// that returns the addresses in a user buffer. The usercall ABI has none.
 pub struct LookupHost(Void);
 
 impl LookupHost {
@@ -516,3 +870,21 @@ pub mod netc {
 
     pub type socklen_t = usize;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helper_limit() {
+        let helpers = AtomicUsize::new(0);
+        for _ in 0..MAX_CONNECT_HELPERS {
+            reserve_helper(&helpers, MAX_CONNECT_HELPERS, "limit").unwrap();
+        }
+        let err = reserve_helper(&helpers, MAX_CONNECT_HELPERS, "limit").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(helpers.load(Ordering::SeqCst), MAX_CONNECT_HELPERS);
+        helpers.fetch_sub(1, Ordering::SeqCst);
+        reserve_helper(&helpers, MAX_CONNECT_HELPERS, "limit").unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+/* Generated by the `test_c_rwlock_header` test in src/libstd/sys/sgx/rwlock.rs.
+ * Do not edit by hand. */
+#ifndef RUST_SGX_RWLOCK_H
+#define RUST_SGX_RWLOCK_H
+
+/* A writer that unlocks the lock while unwinding from a panic that started
+ * while it held the lock poisons the lock. Later successful calls of
+ * `__rust_rwlock_wrlock`, `__rust_rwlock_trywrlock` and
+ * `__rust_rwlock_wrlock_timed` still acquire the lock, but return
+ * `EOWNERDEAD` instead of 0, like a robust pthread mutex. Readers are not
+ * told. A caller that gets `EOWNERDEAD` holds the write lock and must
+ * either repair the state protected by the lock and call
+ * `__rust_rwlock_consistent`, or discard that state; libunwind, whose
+ * caches are protected by these locks, should flush the caches before
+ * calling `__rust_rwlock_consistent`. Until then, every writer gets
+ * `EOWNERDEAD`. */
+
+/* Diagnostics are printed with `__rust_print_err_len(msg, len)`, which
+ * prints exactly `len` bytes, NULs included. `__rust_print_err` stops at
+ * the first NUL and is only kept for older libunwind builds. */
+
+#define RUST_SGX_RWLOCK_SIZE 128
+#define RUST_SGX_RWLOCK_ALIGN 8
+#define RUST_SGX_RWLOCK_INITIALIZER { \
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, \
+}
+
+#endif
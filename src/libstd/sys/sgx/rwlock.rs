@@ -3,30 +3,92 @@ use crate::alloc::{self, Layout};
 use crate::num::NonZeroUsize;
 #[cfg(not(test))]
 use crate::slice;
-#[cfg(not(test))]
-use crate::str;
+use crate::thread;
+use crate::time::Duration;
 
+use super::abort::{abort_with_code, AbortReason};
+use super::deadlock;
+use super::ext::perf;
 use super::waitqueue::{
     try_lock_or_false, NotifiedTcs, SpinMutex, SpinMutexGuard, WaitQueue, WaitVariable,
 };
 use crate::mem;
 
+/// A reader-writer lock built on `WaitQueue`.
+///
+/// The libunwind port statically initializes and locks values of this type
+/// through the `__rust_rwlock_*` functions below, treating it as an opaque
+/// blob of `RWLOCK_SIZE` bytes (see `src/UnwindRustSgx.h` in libunwind). To
+/// keep that contract stable, `RWLock` and all types it is made of are
+/// `repr(C)`. On 64-bit targets, the layout is:
+///
+/// ```text
+/// offset  size  field
+/// 0x00    0x40  readers: SpinMutex<WaitVariable<Option<NonZeroUsize>>>
+/// 0x00    0x30    queue: WaitQueue (list sentinel, 0x01 when uninitialized)
+/// 0x20    0x01      niche of the uninitialized `head_tail_entry` (0x03)
+/// 0x30    0x08    lock: number of active readers
+/// 0x38    0x01    spinlock
+/// 0x40    0x40  writer: SpinMutex<WaitVariable<WriterState>>
+/// 0x40    0x30    queue: WaitQueue (same as above)
+/// 0x70    0x03    lock: WriterState
+/// 0x78    0x01    spinlock
+/// ```
+///
+/// On 32-bit targets, all offsets and pointer-sized fields are halved.
+///
+/// The lock is phase-fair: readers arriving while a writer holds or waits for
+/// the lock are queued, and when a writer releases the lock, all queued
+/// readers are let in before the next writer. This way, neither readers nor
+/// writers can be starved by a continuous stream of the other kind.
+#[repr(C)]
 pub struct RWLock {
     readers: SpinMutex<WaitVariable<Option<NonZeroUsize>>>,
-    writer: SpinMutex<WaitVariable<bool>>,
+    writer: SpinMutex<WaitVariable<WriterState>>,
+}
+
+#[repr(C)]
+struct WriterState {
+    /// Whether a writer holds the lock
+    locked: bool,
+    /// Whether a writer unlocked the lock from the C side while unwinding
+    /// from a panic that started after it acquired the lock
+    poisoned: bool,
+    /// Whether the current writer was already panicking when it acquired the
+    /// lock from the C side
+    locked_while_panicking: bool,
 }
 
-// Below is to check at compile time, that RWLock has size of 128 bytes.
+// The size and alignment of `RWLock` as seen by the libunwind port. These,
+// along with the initializer, are exported to libunwind through `rwlock.h`,
+// which is checked against the Rust definitions by the tests below.
+#[cfg(target_pointer_width = "64")]
+const RWLOCK_SIZE: usize = 128;
+#[cfg(target_pointer_width = "32")]
+const RWLOCK_SIZE: usize = 64;
+const RWLOCK_ALIGN: usize = mem::size_of::<usize>();
+
+// Below is to check at compile time, that RWLock has size of `RWLOCK_SIZE`.
 #[allow(dead_code)]
 unsafe fn rw_lock_size_assert(r: RWLock) {
-    mem::transmute::<RWLock, [u8; 128]>(r);
+    mem::transmute::<RWLock, [u8; RWLOCK_SIZE]>(r);
 }
 
+// Below is to check at compile time, that RWLock has alignment of
+// `RWLOCK_ALIGN`. The array length expression underflows if the alignment is
+// too small, and the types mismatch if it is too large.
+#[allow(dead_code)]
+const RW_LOCK_ALIGN_ASSERT: [(); 0] = [(); mem::align_of::<RWLock>() - RWLOCK_ALIGN];
+
 impl RWLock {
     pub const fn new() -> RWLock {
         RWLock {
             readers: SpinMutex::new(WaitVariable::new(None)),
-            writer: SpinMutex::new(WaitVariable::new(false)),
+            writer: SpinMutex::new(WaitVariable::new(WriterState {
+                locked: false,
+                poisoned: false,
+                locked_while_panicking: false,
+            })),
         }
     }
 
@@ -34,29 +96,60 @@ impl RWLock {
     pub unsafe fn read(&self) {
         let mut rguard = self.readers.lock();
         let wguard = self.writer.lock();
-        if *wguard.lock_var() || !wguard.queue_empty() {
+        if wguard.lock_var().locked || !wguard.queue_empty() {
             // Another thread has or is waiting for the write lock, wait
             drop(wguard);
-            WaitQueue::wait(rguard);
+            deadlock::blocking(self);
+            WaitQueue::wait(rguard, &perf::RWLOCK);
+            deadlock::unblocked();
             // Another thread has passed the lock to us
         } else {
             // No waiting writers, acquire the read lock
             *rguard.lock_var_mut() =
                 NonZeroUsize::new(rguard.lock_var().map_or(0, |n| n.get()) + 1);
         }
+        perf::RWLOCK.acquired();
+    }
+
+    /// Like `read`, but gives up after `dur` has elapsed. Returns `true` if
+    /// the lock was obtained.
+    #[inline]
+    #[cfg_attr(test, allow(dead_code))]
+    pub unsafe fn read_timeout(&self, dur: Duration) -> bool {
+        let mut rguard = self.readers.lock();
+        let wguard = self.writer.lock();
+        if wguard.lock_var().locked || !wguard.queue_empty() {
+            // Another thread has or is waiting for the write lock, wait
+            drop(wguard);
+            deadlock::blocking(self);
+            let success = WaitQueue::wait_timeout(rguard, dur, &perf::RWLOCK);
+            deadlock::unblocked();
+            // If woken up, another thread has passed the lock to us
+            if success {
+                perf::RWLOCK.acquired();
+            }
+            success
+        } else {
+            // No waiting writers, acquire the read lock
+            *rguard.lock_var_mut() =
+                NonZeroUsize::new(rguard.lock_var().map_or(0, |n| n.get()) + 1);
+            perf::RWLOCK.acquired();
+            true
+        }
     }
 
     #[inline]
     pub unsafe fn try_read(&self) -> bool {
         let mut rguard = try_lock_or_false!(self.readers);
         let wguard = try_lock_or_false!(self.writer);
-        if *wguard.lock_var() || !wguard.queue_empty() {
+        if wguard.lock_var().locked || !wguard.queue_empty() {
             // Another thread has or is waiting for the write lock
             false
         } else {
             // No waiting writers, acquire the read lock
             *rguard.lock_var_mut() =
                 NonZeroUsize::new(rguard.lock_var().map_or(0, |n| n.get()) + 1);
+            perf::RWLOCK.acquired();
             true
         }
     }
@@ -65,14 +158,61 @@ impl RWLock {
     pub unsafe fn write(&self) {
         let rguard = self.readers.lock();
         let mut wguard = self.writer.lock();
-        if *wguard.lock_var() || rguard.lock_var().is_some() {
+        if wguard.lock_var().locked || rguard.lock_var().is_some() {
             // Another thread has the lock, wait
             drop(rguard);
-            WaitQueue::wait(wguard);
+            deadlock::blocking(self);
+            WaitQueue::wait(wguard, &perf::RWLOCK);
+            deadlock::unblocked();
             // Another thread has passed the lock to us
         } else {
             // We are just now obtaining the lock
-            *wguard.lock_var_mut() = true;
+            wguard.lock_var_mut().locked = true;
+        }
+        deadlock::acquired(self);
+        perf::RWLOCK.acquired();
+    }
+
+    /// Like `write`, but gives up after `dur` has elapsed. Returns `true` if
+    /// the lock was obtained.
+    #[inline]
+    #[cfg_attr(test, allow(dead_code))]
+    pub unsafe fn write_timeout(&self, dur: Duration) -> bool {
+        let rguard = self.readers.lock();
+        let mut wguard = self.writer.lock();
+        if wguard.lock_var().locked || rguard.lock_var().is_some() {
+            // Another thread has the lock, wait
+            drop(rguard);
+            deadlock::blocking(self);
+            let success = WaitQueue::wait_timeout(wguard, dur, &perf::RWLOCK);
+            deadlock::unblocked();
+            if success {
+                // Another thread has passed the lock to us
+                deadlock::acquired(self);
+                perf::RWLOCK.acquired();
+                return true;
+            }
+            // Readers that arrived while we were waiting are queued behind
+            // us. If no other writer is around, let them proceed.
+            let rguard = self.readers.lock();
+            let wguard = self.writer.lock();
+            if !wguard.lock_var().locked && wguard.queue_empty() {
+                if let Ok(mut rguard) = WaitQueue::notify_all(rguard) {
+                    if let NotifiedTcs::All { count } = rguard.notified_tcs() {
+                        let readers = rguard.lock_var().map_or(0, |n| n.get());
+                        *rguard.lock_var_mut() = NonZeroUsize::new(readers + count.get())
+                    } else {
+                        unreachable!() // called notify_all
+                    }
+                }
+            }
+            false
+        } else {
+            // We are just now obtaining the lock
+            wguard.lock_var_mut().locked = true;
+            deadlock::acquired(self);
+            perf::RWLOCK.acquired();
+            true
         }
     }
 
@@ -80,12 +220,14 @@ impl RWLock {
     pub unsafe fn try_write(&self) -> bool {
         let rguard = try_lock_or_false!(self.readers);
         let mut wguard = try_lock_or_false!(self.writer);
-        if *wguard.lock_var() || rguard.lock_var().is_some() {
+        if wguard.lock_var().locked || rguard.lock_var().is_some() {
             // Another thread has the lock
             false
         } else {
             // We are just now obtaining the lock
-            *wguard.lock_var_mut() = true;
+            wguard.lock_var_mut().locked = true;
+            deadlock::acquired(self);
+            perf::RWLOCK.acquired();
             true
         }
     }
@@ -94,7 +236,7 @@ impl RWLock {
     unsafe fn __read_unlock(
         &self,
         mut rguard: SpinMutexGuard<'_, WaitVariable<Option<NonZeroUsize>>>,
-        wguard: SpinMutexGuard<'_, WaitVariable<bool>>,
+        wguard: SpinMutexGuard<'_, WaitVariable<WriterState>>,
     ) {
         *rguard.lock_var_mut() = NonZeroUsize::new(rguard.lock_var().unwrap().get() - 1);
         if rguard.lock_var().is_some() {
@@ -102,10 +244,17 @@ impl RWLock {
         } else {
             if let Ok(mut wguard) = WaitQueue::notify_one(wguard) {
                 // A writer was waiting, pass the lock
-                *wguard.lock_var_mut() = true;
+                wguard.lock_var_mut().locked = true;
+            } else if let Ok(mut rguard) = WaitQueue::notify_all(rguard) {
+                // Readers were queued behind a writer that has since timed
+                // out, pass the lock to them
+                if let NotifiedTcs::All { count } = rguard.notified_tcs() {
+                    *rguard.lock_var_mut() = Some(count)
+                } else {
+                    unreachable!() // called notify_all
+                }
             } else {
                 // No writers were waiting, the lock is released
-                rtassert!(rguard.queue_empty());
             }
         }
     }
@@ -121,23 +270,57 @@ impl RWLock {
     unsafe fn __write_unlock(
         &self,
         rguard: SpinMutexGuard<'_, WaitVariable<Option<NonZeroUsize>>>,
-        wguard: SpinMutexGuard<'_, WaitVariable<bool>>,
+        mut wguard: SpinMutexGuard<'_, WaitVariable<WriterState>>,
     ) {
-        if let Err(mut wguard) = WaitQueue::notify_one(wguard) {
-            // No writers waiting, release the write lock
-            *wguard.lock_var_mut() = false;
-            if let Ok(mut rguard) = WaitQueue::notify_all(rguard) {
-                // One or more readers were waiting, pass the lock to them
+        deadlock::released(self);
+        match WaitQueue::notify_all(rguard) {
+            Ok(mut rguard) => {
+                // One or more readers were waiting, pass the lock to them.
+                // Any waiting writers will get the lock once this batch of
+                // readers is done.
+                wguard.lock_var_mut().locked = false;
                 if let NotifiedTcs::All { count } = rguard.notified_tcs() {
                     *rguard.lock_var_mut() = Some(count)
                 } else {
                     unreachable!() // called notify_all
                 }
-            } else {
-                // No readers waiting, the lock is released
             }
-        } else {
-            // There was a thread waiting for write, just pass the lock
+            Err(_rguard) => {
+                if let Err(mut wguard) = WaitQueue::notify_one(wguard) {
+                    // No writers waiting, the lock is released
+                    wguard.lock_var_mut().locked = false;
+                } else {
+                    // There was a thread waiting for write, just pass the lock
+                }
+            }
+        }
+    }
+
+    /// Converts the write lock held by the calling thread into a read lock.
+    ///
+    /// No other writer can obtain the lock in between. Readers that were
+    /// queued behind the writer are let in too.
+    #[inline]
+    pub unsafe fn downgrade(&self) {
+        let rguard = self.readers.lock();
+        let mut wguard = self.writer.lock();
+        if !wguard.lock_var().locked {
+            abort_with_code(AbortReason::RwLockMisuse);
+        }
+        deadlock::released(self);
+        wguard.lock_var_mut().locked = false;
+        drop(wguard);
+        match WaitQueue::notify_all(rguard) {
+            Ok(mut rguard) => {
+                if let NotifiedTcs::All { count } = rguard.notified_tcs() {
+                    *rguard.lock_var_mut() = NonZeroUsize::new(count.get() + 1)
+                } else {
+                    unreachable!() // called notify_all
+                }
+            }
+            Err(mut rguard) => {
+                *rguard.lock_var_mut() = NonZeroUsize::new(1);
+            }
         }
     }
 
@@ -148,13 +331,47 @@ impl RWLock {
         self.__write_unlock(rguard, wguard);
     }
 
+    // only used by the C entry points below, after the lock has been
+    // acquired. Returns whether a writer acquired a poisoned lock, readers
+    // aren't told, like with pthread. For writers, also records whether the
+    // thread is already panicking, so that unlocking during that same panic
+    // doesn't poison the lock.
+    #[inline]
+    #[cfg_attr(test, allow(dead_code))]
+    unsafe fn c_acquired(&self, write: bool) -> bool {
+        let mut wguard = self.writer.lock();
+        if !write {
+            return false;
+        }
+        wguard.lock_var_mut().locked_while_panicking = thread::panicking();
+        wguard.lock_var().poisoned
+    }
+
+    // only used by __rust_rwlock_consistent below. Clears the poison of a
+    // write-locked lock, returns `false` if the lock isn't write-locked.
+    #[inline]
+    #[cfg_attr(test, allow(dead_code))]
+    unsafe fn consistent(&self) -> bool {
+        let mut wguard = self.writer.lock();
+        if !wguard.lock_var().locked {
+            return false;
+        }
+        wguard.lock_var_mut().poisoned = false;
+        true
+    }
+
     // only used by __rust_rwlock_unlock below
     #[inline]
     #[cfg_attr(test, allow(dead_code))]
     unsafe fn unlock(&self) {
         let rguard = self.readers.lock();
-        let wguard = self.writer.lock();
-        if *wguard.lock_var() == true {
+        let mut wguard = self.writer.lock();
+        if wguard.lock_var().locked {
+            if thread::panicking() && !wguard.lock_var().locked_while_panicking {
+                // The panic started while the lock was held, so whatever it
+                // protects might be inconsistent
+                wguard.lock_var_mut().poisoned = true;
+            }
             self.__write_unlock(rguard, wguard);
         } else {
             self.__read_unlock(rguard, wguard);
@@ -167,6 +384,34 @@ impl RWLock {
 
 #[cfg(not(test))]
 const EINVAL: i32 = 22;
+#[cfg(not(test))]
+const EBUSY: i32 = 16;
+#[cfg(not(test))]
+const ETIMEDOUT: i32 = 110;
+#[cfg(not(test))]
+const EOWNERDEAD: i32 = 130;
+
+// The result of successfully acquiring `lock` from the C side. Like a robust
+// pthread mutex, a poisoned lock is still acquired for writing, but
+// `EOWNERDEAD` is returned to tell the caller that the state it protects may
+// be inconsistent. The lock stays poisoned until the caller repairs the state
+// and calls `__rust_rwlock_consistent`, see `rwlock.h`.
+#[cfg(not(test))]
+unsafe fn c_lock_result(lock: &RWLock, write: bool) -> i32 {
+    if lock.c_acquired(write) {
+        EOWNERDEAD
+    } else {
+        0
+    }
+}
+
+// Converts an absolute deadline in nanoseconds, as returned by the
+// `insecure_time` usercall, into a timeout relative to the current time.
+#[cfg(not(test))]
+fn timeout_from_deadline(deadline: u64) -> Duration {
+    let deadline = Duration::new(deadline / 1_000_000_000, (deadline % 1_000_000_000) as _);
+    deadline.checked_sub(super::abi::usercalls::insecure_time()).unwrap_or_default()
+}
 
 // used by libunwind port
 #[cfg(not(test))]
@@ -176,7 +421,7 @@ pub unsafe extern "C" fn __rust_rwlock_rdlock(p: *mut RWLock) -> i32 {
         return EINVAL;
     }
     (*p).read();
-    return 0;
+    c_lock_result(&*p, false)
 }
 
 #[cfg(not(test))]
@@ -186,8 +431,80 @@ pub unsafe extern "C" fn __rust_rwlock_wrlock(p: *mut RWLock) -> i32 {
         return EINVAL;
     }
     (*p).write();
+    c_lock_result(&*p, true)
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_rwlock_tryrdlock(p: *mut RWLock) -> i32 {
+    if p.is_null() {
+        return EINVAL;
+    }
+    if (*p).try_read() {
+        c_lock_result(&*p, false)
+    } else {
+        EBUSY
+    }
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_rwlock_trywrlock(p: *mut RWLock) -> i32 {
+    if p.is_null() {
+        return EINVAL;
+    }
+    if (*p).try_write() {
+        c_lock_result(&*p, true)
+    } else {
+        EBUSY
+    }
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_rwlock_rdlock_timed(p: *mut RWLock, deadline: u64) -> i32 {
+    if p.is_null() {
+        return EINVAL;
+    }
+    if (*p).read_timeout(timeout_from_deadline(deadline)) {
+        c_lock_result(&*p, false)
+    } else {
+        ETIMEDOUT
+    }
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_rwlock_wrlock_timed(p: *mut RWLock, deadline: u64) -> i32 {
+    if p.is_null() {
+        return EINVAL;
+    }
+    if (*p).write_timeout(timeout_from_deadline(deadline)) {
+        c_lock_result(&*p, true)
+    } else {
+        ETIMEDOUT
+    }
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_rwlock_downgrade(p: *mut RWLock) -> i32 {
+    if p.is_null() {
+        return EINVAL;
+    }
+    (*p).downgrade();
     return 0;
 }
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_rwlock_consistent(p: *mut RWLock) -> i32 {
+    if p.is_null() || !(*p).consistent() {
+        return EINVAL;
+    }
+    return 0;
+}
+
 #[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn __rust_rwlock_unlock(p: *mut RWLock) -> i32 {
@@ -206,42 +523,113 @@ pub unsafe extern "C" fn __rust_print_err(m: *mut u8, s: i32) {
     if s < 0 {
         return;
     }
+    // Kept for libunwind builds that predate `__rust_print_err_len`. They
+    // pass NUL-terminated messages, and `s` is not guaranteed to be the
+    // length of the message, so stop at the first NUL.
     let buf = slice::from_raw_parts(m as *const u8, s as _);
-    if let Ok(s) = str::from_utf8(&buf[..buf.iter().position(|&b| b == 0).unwrap_or(buf.len())]) {
-        eprint!("{}", s);
+    super::abort::print_err(&buf[..buf.iter().position(|&b| b == 0).unwrap_or(buf.len())]);
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_print_err_len(m: *const u8, len: usize) {
+    if m.is_null() {
+        return;
     }
+    super::abort::print_err(slice::from_raw_parts(m, len));
 }
 
 #[cfg(not(test))]
 #[no_mangle]
 // NB. used by both libunwind and libpanic_abort
 pub unsafe extern "C" fn __rust_abort() {
-    crate::sys::abort_internal();
+    abort_with_code(AbortReason::PanicRuntime);
+}
+
+// The allocation shims go through the global allocator, which may be one
+// installed with `#[global_allocator]` instead of `System`. The global
+// allocator doesn't support zero-sized blocks, so they are represented by a
+// dangling pointer, and invalid layouts make the shims return null.
+#[cfg(not(test))]
+unsafe fn c_alloc(size: usize, align: usize, alloc_fn: unsafe fn(Layout) -> *mut u8) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(_) if size == 0 => align as *mut u8,
+        Ok(layout) => alloc_fn(layout),
+        Err(_) => crate::ptr::null_mut(),
+    }
 }
 
 #[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn __rust_c_alloc(size: usize, align: usize) -> *mut u8 {
-    alloc::alloc(Layout::from_size_align_unchecked(size, align))
+    c_alloc(size, align, alloc::alloc)
+}
+
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_c_alloc_zeroed(size: usize, align: usize) -> *mut u8 {
+    c_alloc(size, align, alloc::alloc_zeroed)
+}
+
+// Like `calloc`, returns null if `count * size` overflows.
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_c_calloc(count: usize, size: usize, align: usize) -> *mut u8 {
+    match count.checked_mul(size) {
+        Some(size) => c_alloc(size, align, alloc::alloc_zeroed),
+        None => crate::ptr::null_mut(),
+    }
+}
+
+// Like `realloc`, except that the caller passes the current size, and the
+// original block is left alone if null is returned.
+#[cfg(not(test))]
+#[no_mangle]
+pub unsafe extern "C" fn __rust_c_realloc(ptr: *mut u8, size: usize, align: usize,
+                                          new_size: usize) -> *mut u8 {
+    let layout = match Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => return crate::ptr::null_mut(),
+    };
+    if Layout::from_size_align(new_size, align).is_err() {
+        return crate::ptr::null_mut();
+    }
+    if size == 0 {
+        return c_alloc(new_size, align, alloc::alloc);
+    }
+    if new_size == 0 {
+        alloc::dealloc(ptr, layout);
+        return align as *mut u8;
+    }
+    alloc::realloc(ptr, layout, new_size)
 }
 
 #[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn __rust_c_dealloc(ptr: *mut u8, size: usize, align: usize) {
-    alloc::dealloc(ptr, Layout::from_size_align_unchecked(size, align))
+    match Layout::from_size_align(size, align) {
+        Ok(_) if size == 0 => {}
+        Ok(layout) => alloc::dealloc(ptr, layout),
+        Err(_) => rtabort!("__rust_c_dealloc called with an invalid layout"),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use core::array::FixedSizeArray;
+    use crate::fmt::Write;
     use crate::mem::{self, MaybeUninit};
+    use crate::sync::Arc;
+    use crate::sync::atomic::{AtomicBool, Ordering};
+    use crate::thread;
 
     // Verify that the bytes of initialized RWLock are the same as in
     // libunwind. If they change, `src/UnwindRustSgx.h` in libunwind needs to
     // be changed too.
     #[test]
     fn test_c_rwlock_initializer() {
+        #[cfg(target_pointer_width = "64")]
         const RWLOCK_INIT: &[u8] = &[
             0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
             0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
@@ -261,6 +649,27 @@ mod tests {
             0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
         ];
 
+        // Same layout as above, with all pointer-sized fields halved.
+        #[cfg(target_pointer_width = "32")]
+        const RWLOCK_INIT: &[u8] = &[
+            0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x3, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x3, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+            0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0,
+        ];
+
+        // If the assertion below fails, that might just be an issue with the
+        // test code and not with the value of RWLOCK_INIT, see
+        // `rwlock_init_bytes`.
+        assert_eq!(rwlock_init_bytes().as_slice(), RWLOCK_INIT);
+    }
+
+    // Returns the bytes of a newly initialized RWLock.
+    fn rwlock_init_bytes() -> [u8; RWLOCK_SIZE] {
         #[inline(never)]
         fn zero_stack() {
             test::black_box(MaybeUninit::<[RWLock; 16]>::zeroed());
@@ -273,16 +682,144 @@ mod tests {
 
         unsafe {
             // try hard to make sure that the padding/unused bytes in RWLock
-            // get initialized as 0. If the assertion below fails, that might
-            // just be an issue with the test code and not with the value of
-            // RWLOCK_INIT.
+            // get initialized as 0.
             zero_stack();
             let mut init = MaybeUninit::<RWLock>::zeroed();
             rwlock_new(&mut init);
-            assert_eq!(
-                mem::transmute::<_, [u8; 128]>(init.assume_init()).as_slice(),
-                RWLOCK_INIT
-            )
+            mem::transmute::<_, [u8; RWLOCK_SIZE]>(init.assume_init())
+        }
+    }
+
+    // Renders the C definitions libunwind uses to statically allocate and
+    // initialize RWLocks.
+    fn c_header(init: &[u8]) -> String {
+        let mut h = String::new();
+        writeln!(h, "/* Generated by the `test_c_rwlock_header` test in \
+                     src/libstd/sys/sgx/rwlock.rs.").unwrap();
+        writeln!(h, " * Do not edit by hand. */").unwrap();
+        writeln!(h, "#ifndef RUST_SGX_RWLOCK_H").unwrap();
+        writeln!(h, "#define RUST_SGX_RWLOCK_H").unwrap();
+        writeln!(h).unwrap();
+        for line in &[
+            "/* A writer that unlocks the lock while unwinding from a panic that started",
+            " * while it held the lock poisons the lock. Later successful calls of",
+            " * `__rust_rwlock_wrlock`, `__rust_rwlock_trywrlock` and",
+            " * `__rust_rwlock_wrlock_timed` still acquire the lock, but return",
+            " * `EOWNERDEAD` instead of 0, like a robust pthread mutex. Readers are not",
+            " * told. A caller that gets `EOWNERDEAD` holds the write lock and must",
+            " * either repair the state protected by the lock and call",
+            " * `__rust_rwlock_consistent`, or discard that state; libunwind, whose",
+            " * caches are protected by these locks, should flush the caches before",
+            " * calling `__rust_rwlock_consistent`. Until then, every writer gets",
+            " * `EOWNERDEAD`. */",
+            "",
+            "/* Diagnostics are printed with `__rust_print_err_len(msg, len)`, which",
+            " * prints exactly `len` bytes, NULs included. `__rust_print_err` stops at",
+            " * the first NUL and is only kept for older libunwind builds. */",
+        ] {
+            writeln!(h, "{}", line).unwrap();
+        }
+        writeln!(h).unwrap();
+        writeln!(h, "#define RUST_SGX_RWLOCK_SIZE {}", RWLOCK_SIZE).unwrap();
+        writeln!(h, "#define RUST_SGX_RWLOCK_ALIGN {}", RWLOCK_ALIGN).unwrap();
+        writeln!(h, "#define RUST_SGX_RWLOCK_INITIALIZER {{ \\").unwrap();
+        for line in init.chunks(8) {
+            let bytes: Vec<_> = line.iter().map(|b| format!("{:#04x},", b)).collect();
+            writeln!(h, "    {} \\", bytes.join(" ")).unwrap();
+        }
+        writeln!(h, "}}").unwrap();
+        writeln!(h).unwrap();
+        writeln!(h, "#endif").unwrap();
+        h
+    }
+
+    // The C definitions in `rwlock.h` are generated from the Rust ones, so
+    // the two can't drift apart. If this fails, replace `rwlock.h` with the
+    // header printed below. libunwind's `src/UnwindRustSgx.h` includes it.
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_c_rwlock_header() {
+        let header = c_header(&rwlock_init_bytes());
+        assert!(header == include_str!("rwlock.h"), "generated header:\n{}", header);
+    }
+
+    #[test]
+    fn poison_until_consistent() {
+        let lock = RWLock::new();
+        unsafe {
+            lock.write();
+            lock.writer.lock().lock_var_mut().poisoned = true;
+            assert!(lock.c_acquired(true));
+            lock.write_unlock();
+            lock.read();
+            assert!(!lock.c_acquired(false));
+            assert!(!lock.consistent());
+            lock.read_unlock();
+            lock.write();
+            assert!(lock.c_acquired(true));
+            assert!(lock.consistent());
+            assert!(!lock.c_acquired(true));
+            lock.write_unlock();
+        }
+    }
+
+    #[test]
+    fn downgrade() {
+        let lock = Arc::new(RWLock::new());
+        unsafe {
+            lock.write();
+            lock.downgrade();
+            assert!(!lock.try_write());
+            assert!(lock.try_read());
+            lock.read_unlock();
+        }
+
+        let writer = {
+            let lock = lock.clone();
+            thread::spawn(move || unsafe {
+                lock.write();
+                lock.write_unlock();
+            })
         };
+        // The writer can only proceed once the downgraded lock is released
+        unsafe { lock.read_unlock() };
+        writer.join().unwrap();
+        unsafe {
+            assert!(lock.try_write());
+            lock.write_unlock();
+        }
+    }
+
+    // Many readers continuously taking the lock must not prevent a writer
+    // from ever acquiring it.
+    #[test]
+    fn writer_not_starved_by_readers() {
+        const READERS: usize = 16;
+        const WRITES: usize = 100;
+
+        let lock = Arc::new(RWLock::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..READERS).map(|_| {
+            let lock = lock.clone();
+            let done = done.clone();
+            thread::spawn(move || unsafe {
+                while !done.load(Ordering::Relaxed) {
+                    lock.read();
+                    lock.read_unlock();
+                }
+            })
+        }).collect();
+
+        for _ in 0..WRITES {
+            unsafe {
+                lock.write();
+                lock.write_unlock();
+            }
+        }
+
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
     }
 }
@@ -1,3 +1,8 @@
+// Stack overflows are reported by the exception handler in
+// `abi::exception`: the target enables stack probes, so an overflow faults in
+// the guard page the post-linker leaves below each thread's stack before any
+// memory below it is touched, and the enclave aborts with a "thread stack
+// overflow" message. Nothing needs to be set up per thread.
 pub struct Handler;
 
 impl Handler {
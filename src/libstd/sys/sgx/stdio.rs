@@ -1,8 +1,11 @@
 use fortanix_sgx_abi as abi;
 
-use crate::io;
+use crate::io::{self, IoVec, IoVecMut};
 use crate::sys::fd::FileDesc;
 
+use super::abi::panic::SgxPanicOutput;
+use super::ext::error_output;
+
 pub struct Stdin(());
 pub struct Stdout(());
 pub struct Stderr(());
@@ -22,6 +25,10 @@ impl io::Read for Stdin {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         with_std_fd(abi::FD_STDIN, |fd| fd.read(buf))
     }
+
+    fn read_vectored(&mut self, bufs: &mut [IoVecMut<'_>]) -> io::Result<usize> {
+        with_std_fd(abi::FD_STDIN, |fd| fd.read_vectored(bufs))
+    }
 }
 
 impl Stdout {
@@ -33,6 +40,10 @@ impl io::Write for Stdout {
         with_std_fd(abi::FD_STDOUT, |fd| fd.write(buf))
     }
 
+    fn write_vectored(&mut self, bufs: &[IoVec<'_>]) -> io::Result<usize> {
+        with_std_fd(abi::FD_STDOUT, |fd| fd.write_vectored(bufs))
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         with_std_fd(abi::FD_STDOUT, |fd| fd.flush())
     }
@@ -47,6 +58,10 @@ impl io::Write for Stderr {
         with_std_fd(abi::FD_STDERR, |fd| fd.write(buf))
     }
 
+    fn write_vectored(&mut self, bufs: &[IoVec<'_>]) -> io::Result<usize> {
+        with_std_fd(abi::FD_STDERR, |fd| fd.write_vectored(bufs))
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         with_std_fd(abi::FD_STDERR, |fd| fd.flush())
     }
@@ -59,6 +74,33 @@ pub fn is_ebadf(err: &io::Error) -> bool {
     err.raw_os_error() == Some(abi::Error::BrokenPipe as _)
 }
 
+enum PanicOutput {
+    Debug(SgxPanicOutput),
+    Sink(error_output::Sink),
+}
+
+impl io::Write for PanicOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            PanicOutput::Debug(ref mut out) => out.write(buf),
+            PanicOutput::Sink(sink) => {
+                sink(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            PanicOutput::Debug(ref mut out) => out.flush(),
+            PanicOutput::Sink(_) => Ok(()),
+        }
+    }
+}
+
 pub fn panic_output() -> Option<impl io::Write> {
-    super::abi::panic::SgxPanicOutput::new()
+    match error_output::sink() {
+        Some(sink) => Some(PanicOutput::Sink(sink)),
+        None => SgxPanicOutput::new().map(PanicOutput::Debug),
+    }
 }
@@ -10,16 +10,84 @@ pub struct Thread(task_queue::JoinHandle);
 
 pub const DEFAULT_MIN_STACK_SIZE: usize = 4096;
 
+// How many TCSs are kept waiting for new threads after their thread exited
+const MAX_IDLE: usize = 16;
+// How long an idle TCS waits for a new thread before it exits. Until then,
+// userspace considers the TCS busy, so after a burst of threads exits, up to
+// `MAX_IDLE` TCSs can't be used by other enclaves or `launch_thread` for this
+// long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
 mod task_queue {
+    use fortanix_sgx_abi::EV_UNPARK;
+
+    use crate::io;
+    use crate::panic::{self, AssertUnwindSafe};
+    use crate::ptr::NonNull;
     use crate::sync::{Mutex, MutexGuard, Once};
     use crate::sync::mpsc;
     use crate::boxed::FnBox;
+    use crate::thread;
+    use crate::time::Duration;
+
+    use super::MAX_IDLE;
+    use crate::sys::abi::{thread as tcs, usercalls};
 
-    pub type JoinHandle = mpsc::Receiver<()>;
+    pub type JoinHandle = mpsc::Receiver<thread::Result<()>>;
+
+    #[derive(Default)]
+    pub(super) struct Queue {
+        pub(super) tasks: Vec<Task>,
+        // The addresses of the TCSs waiting for a task. A TCS is removed from
+        // this list when a task is pushed for it.
+        pub(super) idle: Vec<usize>,
+    }
 
     pub(super) struct Task {
         p: Box<dyn FnBox()>,
-        done: mpsc::Sender<()>,
+        done: mpsc::Sender<thread::Result<()>>,
+    }
+
+    impl Queue {
+        // Adds a task, handing it to a TCS whose thread exited if there is
+        // one, instead of asking userspace to enter the enclave on a new TCS.
+        pub(super) fn push(&mut self, task: Task) -> io::Result<()> {
+            match self.idle.pop() {
+                Some(tcs) => {
+                    // If the event is sent before the idle TCS calls the wait
+                    // usercall, the usercall will return immediately.
+                    let tcs = rtunwrap!(Some, NonNull::new(tcs as *mut u8));
+                    rtunwrap!(Ok, usercalls::send(EV_UNPARK, Some(tcs)));
+                }
+                None => unsafe { usercalls::launch_thread()? },
+            }
+            self.tasks.push(task);
+            Ok(())
+        }
+    }
+
+    // Waits for at most `timeout` for a task to be pushed for the current
+    // TCS, whose thread exited. Returns `None` if there already are
+    // `MAX_IDLE` idle TCSs or the timeout elapsed.
+    pub(super) fn wait_for_task(queue: &Mutex<Queue>, timeout: Duration) -> Option<Task> {
+        let tcs = tcs::current().as_ptr() as usize;
+        {
+            let mut queue = queue.lock().unwrap();
+            if queue.idle.len() >= MAX_IDLE {
+                return None;
+            }
+            queue.idle.push(tcs);
+        }
+        // The previous thread may have left an `EV_UNPARK` pending, so check
+        // that a task was actually pushed for this TCS.
+        let claimed = || !queue.lock().unwrap().idle.contains(&tcs);
+        usercalls::wait_timeout(EV_UNPARK, timeout, claimed);
+        let mut queue = queue.lock().unwrap();
+        if let Some(i) = queue.idle.iter().position(|&t| t == tcs) {
+            queue.idle.swap_remove(i);
+            return None; // timed out
+        }
+        Some(rtunwrap!(Some, queue.tasks.pop()))
     }
 
     impl Task {
@@ -29,8 +97,12 @@ mod task_queue {
         }
 
         pub(super) fn run(self) {
-            (self.p)();
-            let _ = self.done.send(());
+            // `thread::Builder` catches panics of the thread's closure, but
+            // it may still panic while setting up the thread. Don't let that
+            // unwind out of the enclave, but pass the payload to `join`.
+            let p = self.p;
+            let result = panic::catch_unwind(AssertUnwindSafe(move || p()));
+            let _ = self.done.send(result);
         }
     }
 
@@ -39,14 +111,18 @@ mod task_queue {
     static TASK_QUEUE_INIT: Once = Once::new();
     #[cfg_attr(test, linkage = "available_externally")]
     #[export_name = "_ZN16__rust_internals3std3sys3sgx6thread10TASK_QUEUEE"]
-    static mut TASK_QUEUE: Option<Mutex<Vec<Task>>> = None;
+    static mut TASK_QUEUE: Option<Mutex<Queue>> = None;
 
-    pub(super) fn lock() -> MutexGuard<'static, Vec<Task>> {
+    pub(super) fn queue() -> &'static Mutex<Queue> {
         unsafe {
             TASK_QUEUE_INIT.call_once(|| TASK_QUEUE = Some(Default::default()) );
-            TASK_QUEUE.as_ref().unwrap().lock().unwrap()
+            TASK_QUEUE.as_ref().unwrap()
         }
     }
+
+    pub(super) fn lock() -> MutexGuard<'static, Queue> {
+        queue().lock().unwrap()
+    }
 }
 
 impl Thread {
@@ -54,18 +130,28 @@ impl Thread {
     pub unsafe fn new(_stack: usize, p: Box<dyn FnBox()>)
         -> io::Result<Thread>
     {
-        let mut queue_lock = task_queue::lock();
-        usercalls::launch_thread()?;
         let (task, handle) = task_queue::Task::new(p);
-        queue_lock.push(task);
+        task_queue::lock().push(task)?;
         Ok(Thread(handle))
     }
 
-    pub(super) fn entry() {
-        let mut pending_tasks = task_queue::lock();
-        let task = rtunwrap!(Some, pending_tasks.pop());
-        drop(pending_tasks); // make sure to not hold the task queue lock longer than necessary
-        task.run()
+    /// Runs a spawned thread on the current TCS. If `idle`, the TCS already
+    /// ran a thread, and first waits for a new thread to be spawned. Returns
+    /// `false` if no thread was run.
+    pub(super) fn entry(idle: bool) -> bool {
+        let task = if idle {
+            match task_queue::wait_for_task(task_queue::queue(), IDLE_TIMEOUT) {
+                Some(task) => task,
+                None => return false,
+            }
+        } else {
+            let mut pending_tasks = task_queue::lock();
+            let task = rtunwrap!(Some, pending_tasks.tasks.pop());
+            drop(pending_tasks); // make sure to not hold the task queue lock longer than necessary
+            task
+        };
+        task.run();
+        true
     }
 
     pub fn yield_now() {
@@ -77,12 +163,19 @@ impl Thread {
         // FIXME: could store this pointer in TLS somewhere
     }
 
-    pub fn sleep(_dur: Duration) {
-        rtabort!("can't sleep"); // FIXME
+    pub fn sleep(dur: Duration) {
+        // Waiting for no events only returns once the timeout has elapsed
+        usercalls::wait_timeout(0, dur, || false);
     }
 
-    pub fn join(self) {
-        let _ = self.0.recv();
+    // Unlike on other platforms, returns the payload of a panic while
+    // setting up the thread, for `JoinInner::join`.
+    pub fn join(self) -> crate::thread::Result<()> {
+        match self.0.recv() {
+            Ok(result) => result,
+            // The task was dropped without running
+            Err(_) => Ok(()),
+        }
     }
 }
 
@@ -91,3 +184,66 @@ pub mod guard {
     pub unsafe fn current() -> Option<Guard> { None }
     pub unsafe fn init() -> Option<Guard> { None }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{mpsc, Arc, Mutex};
+    use crate::sys::abi::thread;
+    use crate::time::Instant;
+
+    #[test]
+    fn setup_panic_is_joined() {
+        let (task, handle) = task_queue::Task::new(Box::new(|| panic!("setup failed")));
+        task.run();
+        let payload = Thread(handle).join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"setup failed"));
+    }
+
+    // Spawn, exit, spawn: the second task runs on the TCS of the first
+    #[test]
+    fn idle_tcs_runs_next_task() {
+        let queue = Arc::new(Mutex::new(task_queue::Queue::default()));
+        let idle = queue.clone();
+        let waiter = crate::thread::spawn(move || {
+            let tcs = thread::current().as_ptr() as usize;
+            let task = task_queue::wait_for_task(&idle, Duration::from_secs(60)).unwrap();
+            task.run();
+            tcs
+        });
+        let tcs = loop {
+            if let Some(&tcs) = queue.lock().unwrap().idle.first() {
+                break tcs;
+            }
+            crate::thread::sleep(Duration::from_millis(1));
+        };
+        let (tx, rx) = mpsc::channel();
+        let (task, handle) = task_queue::Task::new(Box::new(move || {
+            tx.send(thread::current().as_ptr() as usize).unwrap();
+        }));
+        queue.lock().unwrap().push(task).unwrap();
+        Thread(handle).join().unwrap();
+        assert_eq!(rx.recv().unwrap(), tcs);
+        assert_eq!(waiter.join().unwrap(), tcs);
+        assert!(queue.lock().unwrap().idle.is_empty());
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let queue = Mutex::new(task_queue::Queue::default());
+        let start = Instant::now();
+        assert!(task_queue::wait_for_task(&queue, Duration::from_millis(10)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(queue.lock().unwrap().idle.is_empty());
+    }
+
+    #[test]
+    fn max_idle() {
+        let queue = Mutex::new(task_queue::Queue::default());
+        queue.lock().unwrap().idle = (1..=MAX_IDLE).collect();
+        let start = Instant::now();
+        assert!(task_queue::wait_for_task(&queue, Duration::from_secs(60)).is_none());
+        assert!(start.elapsed() < Duration::from_secs(60));
+        assert_eq!(queue.lock().unwrap().idle.len(), MAX_IDLE);
+    }
+}
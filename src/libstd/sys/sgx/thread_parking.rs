@@ -0,0 +1,148 @@
+//! Thread parking using the `EV_UNPARK` event.
+//!
+//! Events sent to a TCS remain pending until that TCS calls the `wait`
+//! usercall. So an `unpark` that happens after the parked thread has
+//! published its TCS, but before it actually waits, is not lost, and no lock
+//! is needed to coordinate the two threads.
+
+use fortanix_sgx_abi::{Tcs, EV_UNPARK, WAIT_INDEFINITE};
+
+use crate::ptr::NonNull;
+use crate::sync::atomic::AtomicUsize;
+use crate::sync::atomic::Ordering::{Relaxed, SeqCst};
+use crate::time::Duration;
+
+use super::abi::{thread, usercalls};
+
+const EMPTY: usize = 0;
+const PARKED: usize = 1;
+const NOTIFIED: usize = 2;
+
+pub struct Parker {
+    state: AtomicUsize,
+    // The address of the TCS of the thread that owns this parker. Written
+    // before `state` is set to `PARKED`.
+    tcs: AtomicUsize,
+}
+
+impl Parker {
+    pub fn new() -> Parker {
+        Parker { state: AtomicUsize::new(EMPTY), tcs: AtomicUsize::new(0) }
+    }
+
+    // Returns `true` if a notification was consumed, `false` if the state is
+    // now `PARKED`.
+    unsafe fn prepare_park(&self) -> bool {
+        if self.state.compare_exchange(NOTIFIED, EMPTY, SeqCst, SeqCst).is_ok() {
+            return true;
+        }
+        self.tcs.store(thread::current().as_ptr() as usize, Relaxed);
+        match self.state.compare_exchange(EMPTY, PARKED, SeqCst, SeqCst) {
+            Ok(_) => false,
+            Err(NOTIFIED) => {
+                // See the generic implementation for why this must be a swap
+                let old = self.state.swap(EMPTY, SeqCst);
+                rtassert!(old == NOTIFIED);
+                true
+            }
+            Err(_) => rtabort!("inconsistent park state"),
+        }
+    }
+
+    // unsafe: must only be called by the thread that owns the parker
+    pub unsafe fn park(&self) {
+        if self.prepare_park() {
+            return;
+        }
+        loop {
+            let eventset = rtunwrap!(Ok, usercalls::wait(EV_UNPARK, WAIT_INDEFINITE));
+            rtassert!(eventset & EV_UNPARK == EV_UNPARK);
+            if self.state.compare_exchange(NOTIFIED, EMPTY, SeqCst, SeqCst).is_ok() {
+                return; // got a notification
+            }
+            // spurious wakeup, go back to sleep
+        }
+    }
+
+    // unsafe: must only be called by the thread that owns the parker
+    pub unsafe fn park_timeout(&self, dur: Duration) {
+        if self.prepare_park() {
+            return;
+        }
+        usercalls::wait_timeout(EV_UNPARK, dur, || self.state.load(SeqCst) == NOTIFIED);
+        match self.state.swap(EMPTY, SeqCst) {
+            NOTIFIED => {} // got a notification
+            PARKED => {} // timed out
+            _ => rtabort!("inconsistent park_timeout state"),
+        }
+    }
+
+    pub fn unpark(&self) {
+        match self.state.swap(NOTIFIED, SeqCst) {
+            EMPTY | NOTIFIED => {} // no one was waiting, or already unparked
+            PARKED => {
+                // If the event is sent before the parked thread calls the
+                // wait usercall, the usercall will return immediately.
+                let tcs: Tcs = rtunwrap!(Some, NonNull::new(self.tcs.load(Relaxed) as *mut u8));
+                rtunwrap!(Ok, usercalls::send(EV_UNPARK, Some(tcs)));
+            }
+            _ => rtabort!("inconsistent state in unpark"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use crate::thread;
+    use crate::time::Instant;
+
+    #[test]
+    fn unpark_before_park() {
+        let parker = Parker::new();
+        parker.unpark();
+        unsafe { parker.park() };
+        parker.unpark();
+        parker.unpark();
+        unsafe { parker.park_timeout(Duration::from_secs(60)) };
+        assert_eq!(parker.state.load(SeqCst), EMPTY);
+    }
+
+    #[test]
+    fn park_timeout_times_out() {
+        let parker = Parker::new();
+        let start = Instant::now();
+        unsafe { parker.park_timeout(Duration::from_millis(10)) };
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(parker.state.load(SeqCst), EMPTY);
+    }
+
+    #[test]
+    fn unpark_other_thread() {
+        let parker = Arc::new(Parker::new());
+        for _ in 0..10 {
+            let unparker = parker.clone();
+            let t = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                unparker.unpark();
+            });
+            unsafe { parker.park() };
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unpark_other_thread_timeout() {
+        let parker = Arc::new(Parker::new());
+        let unparker = parker.clone();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            unparker.unpark();
+        });
+        let start = Instant::now();
+        unsafe { parker.park_timeout(Duration::from_secs(60)) };
+        assert!(start.elapsed() < Duration::from_secs(60));
+        t.join().unwrap();
+    }
+}
@@ -1,4 +1,8 @@
+#[cfg(feature = "sgx-rdtsc")]
+use core::arch::x86_64::_rdtsc;
+
 use crate::time::Duration;
+#[cfg(not(feature = "sgx-tsc-time"))]
 use super::abi::usercalls;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
@@ -9,9 +13,147 @@ pub struct SystemTime(Duration);
 
 pub const UNIX_EPOCH: SystemTime = SystemTime(Duration::from_secs(0));
 
+#[cfg(not(feature = "sgx-tsc-time"))]
+fn now() -> Duration {
+    usercalls::insecure_time()
+}
+
+#[cfg(feature = "sgx-tsc-time")]
+use self::tsc::now;
+
+/// Reads the time-stamp counter, or returns `None` if the standard library
+/// isn't built with the `sgx-rdtsc` feature.
+///
+/// `rdtsc` raises #UD inside enclaves on SGX1 processors, which aborts the
+/// enclave, and there is no way to find out whether it's allowed without
+/// executing it. So it is only executed when the feature promises an SGX2
+/// processor.
+#[inline(always)]
+pub(crate) fn rdtsc() -> Option<u64> {
+    #[cfg(feature = "sgx-rdtsc")]
+    {
+        Some(unsafe { _rdtsc() })
+    }
+    #[cfg(not(feature = "sgx-rdtsc"))]
+    {
+        None
+    }
+}
+
+// With the `sgx-tsc-time` feature, the time returned by the `insecure_time`
+// usercall is extrapolated with the time-stamp counter, so that most calls
+// don't exit the enclave. Once the last reading is older than
+// `REFRESH_NANOS`, the time is read from userspace again and the rate of the
+// counter is measured anew, which bounds the drift.
+//
+// The feature enables `sgx-rdtsc`, so enclaves built with it only run on SGX2
+// processors.
+#[cfg(feature = "sgx-tsc-time")]
+mod tsc {
+    use crate::time::Duration;
+    use super::super::abi::usercalls;
+    use super::super::waitqueue::SpinMutex;
+
+    // How long the first reading is kept to measure the rate of the counter
+    const CALIBRATION_NANOS: u64 = 10_000_000;
+    // How long a reading is extrapolated before reading the time again
+    const REFRESH_NANOS: u64 = 100_000_000;
+
+    #[derive(Copy, Clone)]
+    struct Reading {
+        tsc: u64,
+        nanos: u64,
+    }
+
+    struct Clock {
+        last: Option<Reading>,
+        // Nanoseconds per tick as a 32.32 fixed-point number, `0` if the
+        // rate isn't known
+        scale: u64,
+        // How many ticks `last` can be extrapolated by
+        max_ticks: u64,
+    }
+
+    static CLOCK: SpinMutex<Clock> = SpinMutex::new(Clock::new());
+
+    impl Clock {
+        const fn new() -> Clock {
+            Clock { last: None, scale: 0, max_ticks: 0 }
+        }
+
+        // Returns the time at `tsc` if it can be extrapolated from the last
+        // reading.
+        fn extrapolate(&self, tsc: u64) -> Option<u64> {
+            let last = self.last?;
+            // Wraps around to a large value if the counter went backwards
+            let ticks = tsc.wrapping_sub(last.tsc);
+            if self.scale == 0 || ticks >= self.max_ticks {
+                return None;
+            }
+            Some(last.nanos + ((ticks as u128 * self.scale as u128) >> 32) as u64)
+        }
+
+        fn update(&mut self, tsc: u64, nanos: u64) {
+            let last = match self.last {
+                // Another thread stored a newer reading
+                Some(last) if tsc <= last.tsc => return,
+                Some(last) if nanos > last.nanos => last,
+                // The first reading, or the time went backwards
+                _ => {
+                    self.last = Some(Reading { tsc, nanos });
+                    self.scale = 0;
+                    return;
+                }
+            };
+            let elapsed = nanos - last.nanos;
+            if self.scale == 0 && elapsed < CALIBRATION_NANOS {
+                return;
+            }
+            let scale = ((elapsed as u128) << 32) / (tsc - last.tsc) as u128;
+            if scale == 0 || scale > u64::max_value() as u128 {
+                self.scale = 0;
+            } else {
+                self.scale = scale as u64;
+                self.max_ticks = (((REFRESH_NANOS as u128) << 32) / scale) as u64;
+            }
+            self.last = Some(Reading { tsc, nanos });
+        }
+    }
+
+    pub(super) fn now() -> Duration {
+        // `sgx-tsc-time` enables `sgx-rdtsc`
+        let tsc = rtunwrap!(Some, super::rdtsc());
+        if let Some(nanos) = CLOCK.lock().extrapolate(tsc) {
+            return Duration::from_nanos(nanos);
+        }
+        // Don't hold the lock during the usercall
+        let time = usercalls::insecure_time();
+        CLOCK.lock().update(tsc, time.as_nanos() as u64);
+        time
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn extrapolate() {
+            let mut clock = Clock::new();
+            clock.update(1000, 1_000_000_000);
+            assert_eq!(clock.extrapolate(1001), None);
+            // One tick per nanosecond
+            clock.update(1000 + CALIBRATION_NANOS, 1_000_000_000 + CALIBRATION_NANOS);
+            let last = 1000 + CALIBRATION_NANOS;
+            assert_eq!(clock.extrapolate(last + 500), Some(1_000_000_500 + CALIBRATION_NANOS));
+            assert_eq!(clock.extrapolate(last + REFRESH_NANOS), None);
+            assert_eq!(clock.extrapolate(last - 1), None);
+        }
+    }
+}
+
 impl Instant {
     pub fn now() -> Instant {
-        Instant(usercalls::insecure_time())
+        Instant(now())
     }
 
     pub fn checked_sub_instant(&self, other: &Instant) -> Option<Duration> {
@@ -37,7 +179,7 @@ impl Instant {
 
 impl SystemTime {
     pub fn now() -> SystemTime {
-        SystemTime(usercalls::insecure_time())
+        SystemTime(now())
     }
 
     pub fn sub_time(&self, other: &SystemTime)
@@ -10,30 +10,45 @@
 /// recorded in the enclave. The wakeup event state is protected by a spinlock.
 /// The queue and associated wait state are stored in a `WaitVariable`.
 
+use crate::cell::Cell;
 use crate::ops::{Deref, DerefMut};
 use crate::num::NonZeroUsize;
+use crate::sync::atomic::spin_loop_hint;
+use crate::time::Duration;
 
 use fortanix_sgx_abi::{Tcs, EV_UNPARK, WAIT_INDEFINITE};
 use super::abi::usercalls;
 use super::abi::thread;
+use super::ext::perf::Counters;
 
 use self::unsafe_list::{UnsafeList, UnsafeListEntry};
 pub use self::spin_mutex::{SpinMutex, SpinMutexGuard, try_lock_or_false};
 
 /// An queue entry in a `WaitQueue`.
+#[repr(C)]
 struct WaitEntry {
     /// TCS address of the thread that is waiting
     tcs: Tcs,
     /// Whether this thread has been notified to be awoken
-    wake: bool
+    wake: bool,
+    /// Whether this thread may be blocked in the `wait` usercall, and thus
+    /// needs to be sent a wakeup event when notified
+    asleep: bool,
 }
 
+/// The number of rounds a waiting thread spins, with exponential backoff,
+/// waiting to be notified before it blocks in the `wait` usercall. In total,
+/// this spins for `2^SPIN_ROUNDS - 1` iterations of `PAUSE`, which is on the
+/// order of the cost of an enclave exit.
+const SPIN_ROUNDS: u32 = 6;
+
 /// Data stored with a `WaitQueue` alongside it. This ensures accesses to the
 /// queue and the data are synchronized, since the type itself is not `Sync`.
 ///
 /// Consumers of this API should use a synchronization primitive for shared
 /// access, such as `SpinMutex`.
 #[derive(Default)]
+#[repr(C)]
 pub struct WaitVariable<T> {
     queue: WaitQueue,
     lock: T
@@ -70,7 +85,12 @@ pub enum NotifiedTcs {
 /// a mutex on drop.
 pub struct WaitGuard<'a, T: 'a> {
     mutex_guard: Option<SpinMutexGuard<'a, WaitVariable<T>>>,
-    notified_tcs: NotifiedTcs
+    notified_tcs: NotifiedTcs,
+    /// The target of the wakeup event, `None` meaning all threads
+    target_tcs: Option<Tcs>,
+    /// Whether any of the notified threads may be blocked in the `wait`
+    /// usercall
+    send_event: bool,
 }
 
 /// A queue of threads that are waiting on some synchronization primitive.
@@ -80,6 +100,7 @@ pub struct WaitGuard<'a, T: 'a> {
 /// safe because the waiting thread will not return from that stack frame until
 /// after it is notified. The notifying thread ensures to clean up any
 /// references to the list entries before sending the wakeup event.
+#[repr(C)]
 pub struct WaitQueue {
     // We use an inner Mutex here to protect the data in the face of spurious
     // wakeups.
@@ -114,14 +135,19 @@ impl<'a, T> DerefMut for WaitGuard<'a, T> {
     }
 }
 
+// FIXME: Notifying threads waiting on different queues takes a `send`
+// usercall per thread. The usercall targets either one TCS or all of them, and
+// broadcasting to all would make every other sleeping thread exit the enclave
+// for a spurious wakeup. Coalescing the events needs a `send` that takes a set
+// of TCSs.
 impl<'a, T> Drop for WaitGuard<'a, T> {
     fn drop(&mut self) {
         drop(self.mutex_guard.take());
-        let target_tcs = match self.notified_tcs {
-            NotifiedTcs::Single(tcs) => Some(tcs),
-            NotifiedTcs::All { .. } => None
-        };
-        rtunwrap!(Ok, usercalls::send(EV_UNPARK, target_tcs));
+        if !self.send_event {
+            // All notified threads observed the notification while spinning
+            return;
+        }
+        rtunwrap!(Ok, usercalls::send(EV_UNPARK, self.target_tcs));
     }
 }
 
@@ -136,24 +162,172 @@ impl WaitQueue {
         self.inner.is_empty()
     }
 
+    /// Spins for a while waiting for `entry` to be notified. If that doesn't
+    /// happen, marks the entry as asleep and returns `true`, meaning that the
+    /// caller needs to block in the `wait` usercall.
+    fn should_sleep(entry: &SpinMutex<WaitEntry>) -> bool {
+        for round in 0..SPIN_ROUNDS {
+            if entry.lock().wake {
+                return false;
+            }
+            for _ in 0..(1 << round) {
+                spin_loop_hint();
+            }
+        }
+        let mut entry = entry.lock();
+        entry.asleep = true;
+        !entry.wake
+    }
+
     /// Adds the calling thread to the `WaitVariable`'s wait queue, then wait
     /// until a wakeup event.
     ///
-    /// This function does not return until this thread has been awoken.
-    pub fn wait<T>(mut guard: SpinMutexGuard<'_, WaitVariable<T>>) {
+    /// This function does not return until this thread has been awoken. The
+    /// wait is recorded in `stats`.
+    pub fn wait<T>(mut guard: SpinMutexGuard<'_, WaitVariable<T>>, stats: &Counters) {
         // very unsafe: check requirements of UnsafeList::push
         unsafe {
             let mut entry = UnsafeListEntry::new(SpinMutex::new(WaitEntry {
                 tcs: thread::current(),
-                wake: false
+                wake: false,
+                asleep: false,
             }));
             let entry = guard.queue.inner.push(&mut entry);
             drop(guard);
-            while !entry.lock().wake {
-                // don't panic, this would invalidate `entry` during unwinding
-                let eventset = rtunwrap!(Ok, usercalls::wait(EV_UNPARK, WAIT_INDEFINITE));
-                rtassert!(eventset & EV_UNPARK == EV_UNPARK);
+            stats.contended(|| {
+                if Self::should_sleep(entry) {
+                    let mut wait_usercalls = 0;
+                    while !entry.lock().wake {
+                        // don't panic, this would invalidate `entry` during unwinding
+                        let eventset = rtunwrap!(Ok, usercalls::wait(EV_UNPARK, WAIT_INDEFINITE));
+                        rtassert!(eventset & EV_UNPARK == EV_UNPARK);
+                        wait_usercalls += 1;
+                    }
+                    stats.wait_usercalls(wait_usercalls);
+                }
+            })
+        }
+    }
+
+    /// Adds the calling thread to the `WaitVariable`'s wait queue, then wait
+    /// until a wakeup event or until `timeout` has elapsed.
+    ///
+    /// Returns `true` if this thread has been awoken. Otherwise, the calling
+    /// thread is removed from the wait queue again and `false` is returned.
+    /// The wait is recorded in `stats`.
+    pub fn wait_timeout<T>(
+        mut guard: SpinMutexGuard<'_, WaitVariable<T>>,
+        timeout: Duration,
+        stats: &Counters,
+    ) -> bool {
+        let lock = SpinMutexGuard::mutex(&guard);
+        // very unsafe: check requirements of UnsafeList::push
+        unsafe {
+            let mut entry = UnsafeListEntry::new(SpinMutex::new(WaitEntry {
+                tcs: thread::current(),
+                wake: false,
+                asleep: false,
+            }));
+            let entry_lock = guard.queue.inner.push(&mut entry);
+            drop(guard);
+            stats.contended(|| {
+                if Self::should_sleep(entry_lock) {
+                    // Every wait usercall either returns an event, after which
+                    // `should_wake_up` is called, or times out.
+                    let wait_usercalls = Cell::new(0);
+                    usercalls::wait_timeout(EV_UNPARK, timeout, || {
+                        wait_usercalls.set(wait_usercalls.get() + 1);
+                        entry_lock.lock().wake
+                    });
+                    if !entry_lock.lock().wake {
+                        wait_usercalls.set(wait_usercalls.get() + 1);
+                    }
+                    stats.wait_usercalls(wait_usercalls.get());
+                }
+            });
+            // acquire the wait queue's lock first, in the same order as the
+            // notifying thread, to avoid deadlock.
+            let mut guard = lock.lock();
+            let success = entry_lock.lock().wake;
+            if !success {
+                // nobody is waking us up, so remove our entry from the queue
+                guard.queue.inner.remove(&mut entry);
             }
+            success
+        }
+    }
+
+    /// Adds the calling thread to the wait queues of all `guards`, then waits
+    /// until a wakeup event on any of them, or until `timeout` has elapsed.
+    ///
+    /// Returns the indices of the queues that notified this thread. That can
+    /// be more than one queue, in which case the caller is responsible for
+    /// handling all of these notifications. The calling thread is removed from
+    /// all other queues. If the timeout elapsed without any notification, the
+    /// returned vector is empty.
+    #[allow(dead_code)] // not used outside of tests yet
+    pub fn wait_any<'a, T>(
+        guards: Vec<SpinMutexGuard<'a, WaitVariable<T>>>,
+        timeout: Option<Duration>,
+        stats: &Counters,
+    ) -> Vec<usize> {
+        let locks: Vec<&'a SpinMutex<WaitVariable<T>>> =
+            guards.iter().map(SpinMutexGuard::mutex).collect();
+        // `entries` must not be reallocated while linked into the queues
+        let mut entries: Vec<_> = guards.iter().map(|_| {
+            UnsafeListEntry::new(SpinMutex::new(WaitEntry {
+                tcs: thread::current(),
+                wake: false,
+                asleep: false,
+            }))
+        }).collect();
+        // very unsafe: check requirements of UnsafeList::push
+        unsafe {
+            let entry_locks: Vec<*const SpinMutex<WaitEntry>> = guards.into_iter()
+                .zip(entries.iter_mut())
+                .map(|(mut guard, entry)| guard.queue.inner.push(entry) as *const _)
+                .collect();
+            let wait_usercalls = Cell::new(0);
+            let woken_up = || {
+                entry_locks.iter().any(|&entry_lock| (*entry_lock).lock().wake)
+            };
+            stats.contended(|| {
+                // There may be several notifying threads, so don't bother
+                // spinning and always mark all entries as asleep
+                for &entry_lock in &entry_locks {
+                    (*entry_lock).lock().asleep = true;
+                }
+                match timeout {
+                    None => {
+                        while !woken_up() {
+                            // don't panic, this would invalidate `entries` during unwinding
+                            let eventset =
+                                rtunwrap!(Ok, usercalls::wait(EV_UNPARK, WAIT_INDEFINITE));
+                            rtassert!(eventset & EV_UNPARK == EV_UNPARK);
+                            wait_usercalls.set(wait_usercalls.get() + 1);
+                        }
+                    }
+                    Some(timeout) => if !woken_up() {
+                        usercalls::wait_timeout(EV_UNPARK, timeout, || {
+                            wait_usercalls.set(wait_usercalls.get() + 1);
+                            woken_up()
+                        });
+                    }
+                }
+                stats.wait_usercalls(wait_usercalls.get());
+            });
+            let mut woken = Vec::new();
+            for (i, (lock, entry)) in locks.iter().zip(entries.iter_mut()).enumerate() {
+                // acquire the wait queue's lock first, in the same order as the
+                // notifying thread, to avoid deadlock.
+                let mut guard = lock.lock();
+                if (*entry_locks[i]).lock().wake {
+                    woken.push(i);
+                } else {
+                    guard.queue.inner.remove(entry);
+                }
+            }
+            woken
         }
     }
 
@@ -169,11 +343,14 @@ impl WaitQueue {
             if let Some(entry) = guard.queue.inner.pop() {
                 let mut entry_guard = entry.lock();
                 let tcs = entry_guard.tcs;
+                let send_event = entry_guard.asleep;
                 entry_guard.wake = true;
                 drop(entry);
                 Ok(WaitGuard {
                     mutex_guard: Some(guard),
-                    notified_tcs: NotifiedTcs::Single(tcs)
+                    notified_tcs: NotifiedTcs::Single(tcs),
+                    target_tcs: Some(tcs),
+                    send_event,
                 })
             } else {
                 Err(guard)
@@ -185,21 +362,29 @@ impl WaitQueue {
     /// guard unchanged.
     ///
     /// If at least one waiter is found, a `WaitGuard` is returned which will
-    /// notify all waiters when it is dropped.
+    /// notify all waiters when it is dropped. All waiters are woken up with a
+    /// single `send` usercall. If there is only one waiter, the event is sent
+    /// to that thread only, otherwise it is broadcast to all threads.
     pub fn notify_all<T>(mut guard: SpinMutexGuard<'_, WaitVariable<T>>)
         -> Result<WaitGuard<'_, T>, SpinMutexGuard<'_, WaitVariable<T>>>
     {
         unsafe {
             let mut count = 0;
+            let mut last_tcs = None;
+            let mut send_event = false;
             while let Some(entry) = guard.queue.inner.pop() {
                 count += 1;
                 let mut entry_guard = entry.lock();
+                last_tcs = Some(entry_guard.tcs);
+                send_event |= entry_guard.asleep;
                 entry_guard.wake = true;
             }
             if let Some(count) = NonZeroUsize::new(count) {
                 Ok(WaitGuard {
                     mutex_guard: Some(guard),
-                    notified_tcs: NotifiedTcs::All { count }
+                    notified_tcs: NotifiedTcs::All { count },
+                    target_tcs: if count.get() == 1 { last_tcs } else { None },
+                    send_event,
                 })
             } else {
                 Err(guard)
@@ -214,6 +399,7 @@ mod unsafe_list {
     use crate::ptr::NonNull;
     use crate::mem;
 
+    #[repr(C)]
     pub struct UnsafeListEntry<T> {
         next: NonNull<UnsafeListEntry<T>>,
         prev: NonNull<UnsafeListEntry<T>>,
@@ -237,6 +423,7 @@ mod unsafe_list {
         }
     }
 
+    #[repr(C)]
     pub struct UnsafeList<T> {
         head_tail: NonNull<UnsafeListEntry<T>>,
         head_tail_entry: Option<UnsafeListEntry<T>>,
@@ -340,6 +527,31 @@ mod unsafe_list {
                 Some((*first.as_ptr()).value.as_ref().unwrap())
             }
         }
+
+        /// Removes an entry from the list.
+        ///
+        /// # Safety
+        ///
+        /// The caller must ensure that `entry` has been pushed onto `self`
+        /// prior to this call and has not moved since then.
+        pub unsafe fn remove(&mut self, entry: &mut UnsafeListEntry<T>) {
+            rtassert!(!self.is_empty());
+            // BEFORE:
+            //     /----\ next ---> /-----\ next ---> /----\
+            // ... |prev|           |entry|           |next| ...
+            //     \----/ <--- prev \-----/ <--- prev \----/
+            //
+            // AFTER:
+            //     /----\ next ---> /----\
+            // ... |prev|           |next| ...
+            //     \----/ <--- prev \----/
+            let mut prev = entry.prev;
+            let mut next = entry.next;
+            prev.as_mut().next = next;
+            next.as_mut().prev = prev;
+            entry.next = NonNull::dangling();
+            entry.prev = NonNull::dangling();
+        }
     }
 
     #[cfg(test)]
@@ -390,6 +602,23 @@ mod unsafe_list {
             }
         }
 
+        #[test]
+        fn push_remove() {
+            unsafe {
+                let mut node1 = UnsafeListEntry::new(1234);
+                let mut node2 = UnsafeListEntry::new(4567);
+                let mut node3 = UnsafeListEntry::new(9999);
+                let mut list = UnsafeList::new();
+                list.push(&mut node1);
+                list.push(&mut node2);
+                list.push(&mut node3);
+                list.remove(&mut node2);
+                assert_eq!(list.pop().unwrap(), &1234);
+                list.remove(&mut node3);
+                assert_empty(&mut list);
+            }
+        }
+
         #[test]
         fn cell() {
             unsafe {
@@ -413,6 +642,7 @@ mod spin_mutex {
     use crate::ops::{Deref, DerefMut};
 
     #[derive(Default)]
+    #[repr(C)]
     pub struct SpinMutex<T> {
         value: UnsafeCell<T>,
         lock: AtomicBool,
@@ -471,6 +701,13 @@ mod spin_mutex {
         }
     }
 
+    impl<'a, T> SpinMutexGuard<'a, T> {
+        /// Returns the mutex this guard is locking.
+        pub fn mutex(this: &Self) -> &'a SpinMutex<T> {
+            this.mutex
+        }
+    }
+
     impl<'a, T> Deref for SpinMutexGuard<'a, T> {
         type Target = T;
 
@@ -545,8 +782,39 @@ mod tests {
             assert!(WaitQueue::notify_one(wq2.lock()).is_ok());
         });
 
-        WaitQueue::wait(locked);
+        WaitQueue::wait(locked, &Counters::new());
 
         t1.join().unwrap();
     }
+
+    #[test]
+    fn wait_any() {
+        let queues: Vec<_> = (0..3).map(|_| Arc::new(SpinMutex::<WaitVariable<()>>::default()))
+            .collect();
+        let waiter = {
+            let queues = queues.clone();
+            thread::spawn(move || {
+                let guards = queues.iter().map(|wq| wq.lock()).collect();
+                WaitQueue::wait_any(guards, None, &Counters::new())
+            })
+        };
+        loop {
+            match WaitQueue::notify_one(queues[1].lock()) {
+                Ok(_) => break,
+                Err(_) => thread::yield_now(),
+            }
+        }
+        assert_eq!(waiter.join().unwrap(), vec![1]);
+        // The waiter has removed itself from the other queues
+        assert!(queues.iter().all(|wq| wq.lock().queue_empty()));
+    }
+
+    #[test]
+    fn wait_any_timeout() {
+        let queues: Vec<_> = (0..2).map(|_| SpinMutex::<WaitVariable<()>>::default()).collect();
+        let guards = queues.iter().map(|wq| wq.lock()).collect();
+        let woken = WaitQueue::wait_any(guards, Some(Duration::from_millis(10)), &Counters::new());
+        assert!(woken.is_empty());
+        assert!(queues.iter().all(|wq| wq.lock().queue_empty()));
+    }
 }
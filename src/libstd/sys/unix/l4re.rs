@@ -164,6 +164,10 @@ pub mod net {
             unimpl!();
         }
 
+        pub fn flush(&self) -> io::Result<()> {
+            unimpl!();
+        }
+
         pub fn peer_addr(&self) -> io::Result<SocketAddr> {
             unimpl!();
         }
@@ -52,6 +52,10 @@ impl TcpStream {
         match self.0 {}
     }
 
+    pub fn flush(&self) -> io::Result<()> {
+        match self.0 {}
+    }
+
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         match self.0 {}
     }
@@ -0,0 +1,13 @@
+//! Platform-specific implementations of `std::sync::Barrier`.
+//!
+//! `Barrier::new(n)` creates a barrier for `n` threads, and `wait` blocks
+//! until `n` threads have called it, returning `true` for exactly one of them.
+
+cfg_if! {
+    if #[cfg(all(target_vendor = "fortanix", target_env = "sgx"))] {
+        pub use crate::sys::barrier::Barrier;
+    } else {
+        mod generic;
+        pub use self::generic::Barrier;
+    }
+}
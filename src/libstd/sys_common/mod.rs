@@ -39,6 +39,7 @@ macro_rules! rtunwrap {
 
 pub mod alloc;
 pub mod at_exit_imp;
+pub mod barrier;
 #[cfg(feature = "backtrace")]
 pub mod backtrace;
 pub mod condvar;
@@ -57,6 +58,7 @@ pub mod rwlock;
 pub mod thread;
 pub mod thread_info;
 pub mod thread_local;
+pub mod thread_parker;
 pub mod util;
 pub mod wtf8;
 pub mod bytestring;
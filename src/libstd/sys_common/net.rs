@@ -275,6 +275,10 @@ impl TcpStream {
         self.inner.write_vectored(bufs)
     }
 
+    pub fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         sockname(|buf, len| unsafe {
             c::getpeername(*self.inner.as_inner(), buf, len)
@@ -0,0 +1,14 @@
+//! Platform-specific implementations of the token used by `thread::park` and
+//! `Thread::unpark`.
+//!
+//! Each `Parker` belongs to a single thread. `park` and `park_timeout` may
+//! only be called by that thread, while `unpark` may be called by any thread.
+
+cfg_if! {
+    if #[cfg(all(target_vendor = "fortanix", target_env = "sgx"))] {
+        pub use crate::sys::thread_parking::Parker;
+    } else {
+        mod generic;
+        pub use self::generic::Parker;
+    }
+}
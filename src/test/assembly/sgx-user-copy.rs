@@ -0,0 +1,45 @@
+// assembly-output: emit-asm
+// compile-flags: --crate-type rlib -O
+// only-sgx
+
+// Copies between enclave and user memory must be done with `rep movsb` and
+// `rep movsq`, so that every byte is accessed exactly once. A call to
+// `memcpy`, or a loop the compiler is free to rewrite, would lose that
+// guarantee.
+
+#![feature(sgx_platform)]
+
+use std::os::fortanix_sgx::usercalls::alloc::UserRef;
+
+// CHECK-LABEL: copy_to_user:
+// CHECK-NOT: memcpy
+// CHECK: rep{{;?}} movsb
+// CHECK: rep{{;?}} movsq
+// CHECK: rep{{;?}} movsb
+// CHECK-NOT: memcpy
+#[no_mangle]
+pub fn copy_to_user(dst: &mut UserRef<[u8]>, src: &[u8]) {
+    dst.copy_from_enclave(src)
+}
+
+// CHECK-LABEL: copy_from_user:
+// CHECK-NOT: memcpy
+// CHECK: rep{{;?}} movsb
+// CHECK: rep{{;?}} movsq
+// CHECK: rep{{;?}} movsb
+// CHECK-NOT: memcpy
+#[no_mangle]
+pub fn copy_from_user(src: &UserRef<[u8]>, dst: &mut [u8]) {
+    src.copy_to_enclave(dst)
+}
+
+// CHECK-LABEL: read_user_value:
+// CHECK-NOT: memcpy
+// CHECK: rep{{;?}} movsb
+// CHECK: rep{{;?}} movsq
+// CHECK: rep{{;?}} movsb
+// CHECK-NOT: memcpy
+#[no_mangle]
+pub fn read_user_value(src: &UserRef<u64>) -> u64 {
+    src.read_once()
+}